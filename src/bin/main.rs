@@ -1,24 +1,377 @@
 use chip8::emulator;
+use chip8::emulator::movie::Movie;
+use chip8::emulator::storage;
+use chip8::emulator::ui::antiflicker;
+use chip8::emulator::ui::audio::WavSink;
+use chip8::emulator::ui::config::Config;
+use chip8::emulator::ui::effects::Effects;
+use chip8::emulator::ui::gui;
+use chip8::emulator::ui::keymap::Keymap;
+use chip8::emulator::ui::presets::{Preset, Presets};
+use chip8::emulator::ui::recorder::{DeferredFile, Recorder};
+use chip8::emulator::ui::screenshot;
+use chip8::emulator::ui::session::Session;
+use chip8::emulator::ui::terminal::Output;
 use pretty_env_logger;
 use std::env;
-use std::fs::File;
-use std::io;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_FRAMES: u64 = 60;
+
+/// Movies to play back and/or record.
+#[derive(Default)]
+struct Movies {
+    replay: Option<Movie>,
+    record: Option<PathBuf>,
+}
+
+/// Settings for running without a window.
+#[derive(Debug, Default)]
+struct Headless {
+    screenshot: Option<PathBuf>,
+    record: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    frames: Option<u64>,
+    scale: u32,
+    seed: Option<u64>,
+}
 
 fn main() -> emulator::Result<()> {
     pretty_env_logger::init();
 
-    if let Some(rom_file) = env::args().skip(1).next() {
-        start_emu(rom_file)?
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config = match args.iter().find_map(|arg| arg.strip_prefix("--config=")) {
+        Some(path) => Config::load(Path::new(path))?,
+        None => Config::load_default()?,
+    };
+
+    // settings for the ROM go between the config file and the command line
+    let preset = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(rom) if !args.iter().any(|arg| arg == "--no-preset") => find_preset(Path::new(rom))?,
+        _ => None,
+    };
+
+    let mut platform = emulator::Platform::default();
+    let mut quirk_settings = None;
+    let mut options = gui::Options {
+        palettes: config.palettes()?,
+        buzzer: config.buzzer()?,
+        autofire: config.autofire()?,
+        macros: config.macros()?,
+        ..gui::Options::default()
+    };
+    if let Some(name) = config.platform.as_ref() {
+        platform = parse_platform(name)?;
+    }
+    if let Some(name) = config.anti_flicker.as_ref() {
+        options.anti_flicker = parse_anti_flicker(name)?;
+    }
+    if let Some(name) = config.palette.as_ref() {
+        options.palette = find_palette(&options, name)?;
+    }
+    if let Some(name) = config.scaling.as_ref() {
+        options.scaling = parse_scaling(name)?;
+    }
+    if let Some(fullscreen) = config.fullscreen {
+        options.fullscreen = fullscreen;
+    }
+    if let Some(keypad) = config.keypad {
+        options.keypad = keypad;
+    }
+    if let Some(names) = config.effects.as_ref() {
+        options.effects = parse_effects(names)?;
+    }
+    if let Some(speed) = config.speed {
+        options.speed = speed;
+    }
+    if let Some(p) = preset.as_ref() {
+        if let Some(name) = p.platform.as_ref() {
+            platform = parse_platform(name)?;
+        }
+        if let Some(name) = p.palette.as_ref() {
+            options.palette = find_palette(&options, name)?;
+        }
+        if let Some(speed) = p.speed {
+            options.speed = speed;
+        }
+    }
+
+    let mut headless = Headless {
+        scale: screenshot::DEFAULT_SCALE,
+        ..Headless::default()
+    };
+    let keymaps = config.keymaps()?;
+    let mut keymap = None;
+    let mut movies = Movies::default();
+    let mut terminal = None;
+    let mut rom_file = None;
+    for arg in args {
+        if let Some(name) = arg.strip_prefix("--platform=") {
+            platform = parse_platform(name)?;
+        } else if let Some(settings) = arg.strip_prefix("--quirks=") {
+            quirk_settings = Some(settings.to_string());
+        } else if let Some(name) = arg.strip_prefix("--anti-flicker=") {
+            options.anti_flicker = parse_anti_flicker(name)?;
+        } else if let Some(name) = arg.strip_prefix("--palette=") {
+            options.palette = find_palette(&options, name)?;
+        } else if let Some(name) = arg.strip_prefix("--scaling=") {
+            options.scaling = parse_scaling(name)?;
+        } else if let Some(names) = arg.strip_prefix("--effects=") {
+            options.effects = parse_effects(names)?;
+        } else if arg == "--fullscreen" {
+            options.fullscreen = true;
+        } else if arg == "--keypad" {
+            options.keypad = true;
+        } else if let Some(name) = arg.strip_prefix("--keymap=") {
+            keymap = Some(name.to_string());
+        } else if let Some(path) = arg.strip_prefix("--record-movie=") {
+            movies.record = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix("--replay=") {
+            movies.replay = Some(Movie::load(Path::new(path))?);
+        } else if let Some(n) = arg.strip_prefix("--speed=") {
+            options.speed = parse_number(n)?;
+        } else if arg == "--no-preset" {
+            // already handled
+        } else if arg == "--sound" {
+            options.sound = true;
+        } else if arg == "--mute" {
+            options.sound = false;
+        } else if let Some(path) = arg.strip_prefix("--screenshot=") {
+            headless.screenshot = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix("--record=") {
+            headless.record = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix("--wav=") {
+            headless.wav = Some(PathBuf::from(path));
+        } else if let Some(n) = arg.strip_prefix("--frames=") {
+            headless.frames = Some(parse_number(n)?);
+        } else if let Some(n) = arg.strip_prefix("--scale=") {
+            headless.scale = parse_number(n)?;
+        } else if let Some(n) = arg.strip_prefix("--seed=") {
+            headless.seed = Some(parse_number(n)?);
+        } else if arg == "--terminal" {
+            terminal = Some(Output::default());
+        } else if let Some(name) = arg.strip_prefix("--terminal=") {
+            terminal = Some(parse_output(name)?);
+        } else if arg.starts_with("--config=") {
+            // already loaded
+        } else {
+            rom_file = Some(arg);
+        }
+    }
+
+    if let Some(rom_file) = rom_file {
+        // the command line wins over the ROM's entry in the config file, which wins over the
+        // preset, which wins over the config file's keymap for all ROMs
+        let rom_name = Path::new(&rom_file)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let keymap = keymap
+            .as_deref()
+            .or_else(|| config.rom_keymaps.get(&rom_name).map(String::as_str))
+            .or_else(|| preset.as_ref().and_then(|p| p.keymap.as_deref()))
+            .or(config.keymap.as_deref());
+        if let Some(name) = keymap {
+            options.keymap = find_keymap(&keymaps, name)?;
+        }
+
+        let mut quirks = platform.quirks();
+        if let Some(p) = preset.as_ref() {
+            p.add_keys(&mut options.keymap)?;
+            quirks = p.quirks(quirks)?;
+        }
+        if let Some(settings) = quirk_settings {
+            quirks = quirks.with_settings(&settings).ok_or_else(|| {
+                emulator::Error::InvalidArgument(format!("--quirks={}", settings))
+            })?;
+        }
+
+        start_emu(
+            rom_file, platform, quirks, options, headless, movies, terminal,
+        )?
     }
 
     Ok(())
 }
 
-fn start_emu(rom: String) -> emulator::Result<()> {
+fn parse_platform(name: &str) -> emulator::Result<emulator::Platform> {
+    emulator::Platform::from_name(name)
+        .ok_or_else(|| emulator::Error::InvalidArgument(format!("unknown platform {}", name)))
+}
+
+fn parse_anti_flicker(name: &str) -> emulator::Result<antiflicker::Mode> {
+    antiflicker::Mode::from_name(name).ok_or_else(|| {
+        emulator::Error::InvalidArgument(format!("unknown anti-flicker mode {}", name))
+    })
+}
+
+fn parse_scaling(name: &str) -> emulator::Result<gui::Scaling> {
+    gui::Scaling::from_name(name)
+        .ok_or_else(|| emulator::Error::InvalidArgument(format!("unknown scaling {}", name)))
+}
+
+fn parse_effects(names: &str) -> emulator::Result<Effects> {
+    Effects::from_names(names)
+        .ok_or_else(|| emulator::Error::InvalidArgument(format!("unknown effects {}", names)))
+}
+
+fn parse_output(name: &str) -> emulator::Result<Output> {
+    Output::from_name(name).ok_or_else(|| {
+        emulator::Error::InvalidArgument(format!("unknown terminal output {}", name))
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(n: &str) -> emulator::Result<T> {
+    n.parse()
+        .map_err(|_| emulator::Error::InvalidArgument(format!("invalid number {}", n)))
+}
+
+fn find_palette(
+    options: &gui::Options,
+    name: &str,
+) -> emulator::Result<emulator::ui::palette::Palette> {
+    options
+        .palettes
+        .iter()
+        .find(|p| p.name == name)
+        .cloned()
+        .ok_or_else(|| emulator::Error::InvalidArgument(format!("unknown palette {}", name)))
+}
+
+/// The preset for the ROM at `path`, if there is one.
+fn find_preset(path: &Path) -> emulator::Result<Option<Preset>> {
+    let hash = storage::rom_hash(&fs::read(path)?);
+    let presets = Presets::load_default()?;
+    let preset = presets.find(&hash).cloned();
+    if let Some(name) = preset.as_ref().and_then(|p| p.name.as_ref()) {
+        log::info!("Using the settings for {}", name);
+    }
+    Ok(preset)
+}
+
+fn find_keymap(keymaps: &[Keymap], name: &str) -> emulator::Result<Keymap> {
+    keymaps
+        .iter()
+        .find(|k| k.name == name)
+        .cloned()
+        .ok_or_else(|| emulator::Error::InvalidArgument(format!("unknown keymap {}", name)))
+}
+
+fn start_emu(
+    rom: String,
+    platform: emulator::Platform,
+    quirks: emulator::Quirks,
+    mut options: gui::Options,
+    mut headless: Headless,
+    movies: Movies,
+    terminal: Option<Output>,
+) -> emulator::Result<()> {
+    let windowless =
+        headless.screenshot.is_some() || headless.record.is_some() || headless.wav.is_some();
+    if terminal.is_some() && !windowless && (movies.replay.is_some() || movies.record.is_some()) {
+        return Err(emulator::Error::InvalidArgument(
+            "--terminal cannot record or replay movies".to_string(),
+        ));
+    }
+
+    // a movie has to be played back on the platform it was recorded on
+    let platform = movies.replay.as_ref().map_or(platform, |m| m.platform);
     let input = File::open(rom)?;
     let buffered = io::BufReader::new(input);
-    let mut emu = emulator::Emulator::new(buffered)?;
+    let mut emu = emulator::Emulator::with_platform(buffered, platform)?;
+    emu.set_quirks(quirks);
+    if let Some(store) = storage::FlagStore::for_rom(emu.rom_hash()) {
+        emu.set_flag_store(store);
+    }
+    if let Some(seed) = headless.seed {
+        emu.seed_rng(seed);
+    }
 
-    emulator::ui::gui::start_loop(&mut emu)
-    //emulator::debugger::start(&mut emu)
+    if let Some(movie) = movies.replay.as_ref() {
+        movie.prepare(&mut emu)?;
+        options.speed = movie.speed;
+        headless.frames = headless.frames.or(Some(movie.frames));
+        options.replay = Some(movie.clone());
+    }
+    if let Some(path) = movies.record {
+        let seed = headless.seed.unwrap_or_else(rand::random);
+        let movie = Movie::new(&emu, seed, options.speed);
+        movie.prepare(&mut emu)?;
+        options.record_movie = Some((movie, path));
+    }
+
+    if windowless {
+        return run_headless(&mut emu, &options, &headless);
+    }
+
+    match terminal {
+        Some(output) => emulator::debugger::start(
+            &mut emu,
+            &emulator::debugger::Options {
+                output,
+                palette: options.palette,
+                anti_flicker: options.anti_flicker,
+                scale: headless.scale,
+                speed: options.speed,
+                keymap: options.keymap,
+                autofire: options.autofire,
+                macros: options.macros,
+            },
+        ),
+        None => gui::start_loop(&mut emu, options),
+    }
+}
+
+/// Runs the ROM for a fixed number of frames without a window, recording the picture and/or the
+/// sound and saving the last frame.
+fn run_headless(
+    emu: &mut emulator::Emulator,
+    options: &gui::Options,
+    headless: &Headless,
+) -> emulator::Result<()> {
+    let mut session = Session::new(options.anti_flicker);
+    let mut recorder = headless.record.as_ref().map(|path| {
+        Recorder::new(
+            DeferredFile::new(path.clone()),
+            headless.scale,
+            options.effects,
+        )
+    });
+    if let Some(path) = headless.wav.as_ref() {
+        let wav = WavSink::new(BufWriter::new(File::create(path)?))?;
+        session.set_audio(Box::new(wav));
+        session.set_buzzer(options.buzzer);
+    }
+
+    if let Some(movie) = options.replay.clone() {
+        session.play_movie(movie);
+    }
+    if let Some((movie, path)) = options.record_movie.clone() {
+        session.record_movie(movie, path);
+    }
+
+    let frames = headless.frames.unwrap_or(DEFAULT_FRAMES);
+    while session.frames() < frames && session.run_frame(emu, options.speed)? {
+        if let Some(r) = recorder.as_mut() {
+            r.push(&session.picture(), &options.palette)?;
+        }
+    }
+
+    if let Some(r) = recorder {
+        r.finish()?;
+    }
+    session.finish()?;
+    if let Some(path) = headless.screenshot.as_ref() {
+        screenshot::save(
+            &session.picture(),
+            &options.palette,
+            &options.effects,
+            headless.scale,
+            path,
+        )?;
+    }
+
+    Ok(())
 }
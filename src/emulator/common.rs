@@ -5,11 +5,15 @@ use std::io;
 #[derive(Debug)]
 pub enum Error {
     IOError(io::Error),
+    InvalidArgument(String),
+    InvalidConfig(String),
+    InvalidMovie(String),
     InvalidROM,
     EndOfROM,
     UnknownInstruction(u16),
     StackOverflow,
     StackUnderflow,
+    AddressOutOfRange(usize),
     Unexpected(Box<dyn StdError>),
 }
 
@@ -23,6 +27,11 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::IOError(ref err) => err.fmt(f),
+            Error::InvalidArgument(ref arg) => {
+                f.write_fmt(format_args!("Invalid argument: {}", arg))
+            }
+            Error::InvalidConfig(ref msg) => f.write_fmt(format_args!("Invalid config: {}", msg)),
+            Error::InvalidMovie(ref msg) => f.write_fmt(format_args!("Invalid movie: {}", msg)),
             Error::InvalidROM => f.write_str("Invalid ROM"),
             Error::EndOfROM => f.write_str("End of ROM"),
             Error::UnknownInstruction(i) => {
@@ -30,6 +39,9 @@ impl fmt::Display for Error {
             }
             Error::StackOverflow => f.write_str("Stack overflow"),
             Error::StackUnderflow => f.write_str("Stack underflow"),
+            Error::AddressOutOfRange(addr) => {
+                f.write_fmt(format_args!("Address out of range: {:#X}", addr))
+            }
             Error::Unexpected(ref err) => err.fmt(f),
         }
     }
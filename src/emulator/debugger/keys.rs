@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use termion::event::Key;
+
+// Terminals only report keys going down, repeating them while they are held. A key counts as
+// held until it has not come in for this many frames, which bridges the pause before
+// repeating starts on most systems.
+const HOLD_FRAMES: u64 = 10;
+
+/// The name a [`Keymap`] uses for a terminal key. The numeric keypad cannot be told apart from
+/// the other keys in a terminal.
+///
+/// [`Keymap`]: crate::emulator::ui::keymap::Keymap
+pub fn host_key_name(key: Key) -> Option<String> {
+    let name = match key {
+        Key::Char(' ') => "space",
+        Key::Char('\n') => "enter",
+        Key::Char('\t') => "tab",
+        Key::Backspace => "backspace",
+        Key::Up => "up",
+        Key::Down => "down",
+        Key::Left => "left",
+        Key::Right => "right",
+        Key::Char(c) if c.is_ascii_graphic() => return Some(c.to_ascii_lowercase().to_string()),
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Turns the key presses a terminal reports into keys going down and up.
+#[derive(Debug, Default)]
+pub struct Presses {
+    last: BTreeMap<String, u64>, // held keys, with the frame they last came in
+}
+
+impl Presses {
+    /// Notes a press of the key during `frame`. Returns whether it was up before.
+    pub fn press(&mut self, host: &str, frame: u64) -> bool {
+        self.last.insert(host.to_string(), frame).is_none()
+    }
+
+    /// The keys that have not come in again for long enough to count as released by `frame`.
+    pub fn released(&mut self, frame: u64) -> Vec<String> {
+        let released: Vec<String> = self
+            .last
+            .iter()
+            .filter(|(_, last)| frame - **last >= HOLD_FRAMES)
+            .map(|(host, _)| host.clone())
+            .collect();
+        for host in released.iter() {
+            self.last.remove(host);
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_host_key_name() {
+        assert_eq!(host_key_name(Key::Char('Q')).as_deref(), Some("q"));
+        assert_eq!(host_key_name(Key::Char(';')).as_deref(), Some(";"));
+        assert_eq!(host_key_name(Key::Char(' ')).as_deref(), Some("space"));
+        assert_eq!(host_key_name(Key::Left).as_deref(), Some("left"));
+        assert_eq!(host_key_name(Key::Ctrl('p')), None);
+        assert_eq!(host_key_name(Key::Char('é')), None);
+    }
+
+    #[test]
+    fn test_presses() {
+        let mut presses = Presses::default();
+        assert!(presses.press("w", 0));
+        assert!(presses.released(5).is_empty());
+
+        // repeats keep the key down
+        assert!(!presses.press("w", 8));
+        assert!(presses.press("a", 8));
+        assert!(presses.released(17).is_empty());
+        assert_eq!(presses.released(18), vec!["a", "w"]);
+        assert!(presses.released(30).is_empty());
+    }
+}
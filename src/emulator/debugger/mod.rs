@@ -1,38 +1,209 @@
+mod keys;
+mod screen;
+mod stats;
+
 use crate::emulator;
-use crate::emulator::common::{Error, Result};
-use crossbeam_channel;
-use std::time::Duration;
+use crate::emulator::common::Result;
+use crate::emulator::ui::antiflicker::Mode;
+use crate::emulator::ui::input::{Autofire, InputLayer, Macro};
+use crate::emulator::ui::keymap::Keymap;
+use crate::emulator::ui::palette::Palette;
+use crate::emulator::ui::session::Session;
+use crate::emulator::ui::terminal::Output;
+use crossbeam_channel::{self, select, Receiver};
+use screen::Screen;
+use stats::Meter;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use tui::backend::TermionBackend;
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Color, Style};
+use tui::widgets::{Block, Borders, Paragraph, Text};
+use tui::Terminal;
 
 const CLOCK_SPEED_HZ: u32 = 60;
 
-pub fn start(emu: &mut emulator::Emulator) -> Result<()> {
+const QUIT_KEY: Key = Key::Esc;
+const PAUSE_KEY: Key = Key::Ctrl('p');
+const RESET_KEY: Key = Key::Ctrl('r');
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub output: Output,
+    pub palette: Palette,
+    pub anti_flicker: Mode,
+    pub scale: u32,   // for inline images
+    pub speed: usize, // instructions per frame
+    pub keymap: Keymap,
+    pub autofire: Autofire,
+    pub macros: Vec<Macro>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            output: Output::default(),
+            palette: Palette::default(),
+            anti_flicker: Mode::default(),
+            scale: 4,
+            speed: 1,
+            keymap: Keymap::default(),
+            autofire: Autofire::default(),
+            macros: Vec::new(),
+        }
+    }
+}
+
+/// Runs the emulator full screen in the terminal: the display in a pane, a status line below
+/// it, and the keyboard mapped to the keypad. Ctrl-P pauses, Ctrl-R resets and Esc or Ctrl-C
+/// quits.
+pub fn start(emu: &mut emulator::Emulator, options: &Options) -> Result<()> {
+    let stdout = AlternateScreen::from(io::stdout().into_raw_mode()?);
+    let mut terminal = Terminal::new(TermionBackend::new(stdout))?;
+    terminal.hide_cursor()?;
+
     let ticker = crossbeam_channel::tick(Duration::from_secs(1) / CLOCK_SPEED_HZ);
-    for _tick in ticker.iter() {
-        match emu.step() {
-            Ok(Some(emulator::Step::Draw(pixels))) => draw(pixels),
-            Ok(_) => {}
-            Err(err) => {
-                eprintln!("{}", err);
-                return Err(Error::Unexpected(Box::new(err)));
+    let keys = read_keys();
+    let mut session = Session::new(options.anti_flicker);
+    let mut layer = InputLayer::new(
+        options.keymap.clone(),
+        options.autofire.clone(),
+        options.macros.clone(),
+    );
+    let mut presses = keys::Presses::default();
+    let mut meter = Meter::default();
+    let mut frame = 0;
+    let mut paused = false;
+
+    loop {
+        select! {
+            recv(ticker) -> _ => {
+                if !paused {
+                    let mut changes = Vec::new();
+                    for host in presses.released(frame) {
+                        changes.extend(layer.host_key(&host, false));
+                    }
+                    changes.extend(layer.next_frame());
+                    for (input, down) in changes {
+                        session.key(emu, input, down);
+                    }
+
+                    if !session.run_frame(emu, options.speed)? {
+                        break;
+                    }
+                    frame += 1;
+                }
+
+                meter.update(Instant::now(), frame, emu.cycles());
+                let status = status(&meter, paused, options.speed);
+                draw(&mut terminal, &session, options, &status)?;
             }
+            recv(keys) -> key => match key {
+                Ok(QUIT_KEY) | Ok(Key::Ctrl('c')) | Err(_) => break,
+                Ok(PAUSE_KEY) => paused = !paused,
+                Ok(RESET_KEY) => {
+                    emu.reset();
+                    session = Session::new(options.anti_flicker);
+                    layer = InputLayer::new(
+                        options.keymap.clone(),
+                        options.autofire.clone(),
+                        options.macros.clone(),
+                    );
+                    presses = keys::Presses::default();
+                }
+                Ok(key) => {
+                    if let Some(host) = keys::host_key_name(key) {
+                        if presses.press(&host, frame) {
+                            for (input, down) in layer.host_key(&host, true) {
+                                session.key(emu, input, down);
+                            }
+                        }
+                    }
+                }
+            },
         }
     }
 
+    terminal.show_cursor()?;
     Ok(())
 }
 
-fn draw(pixels: emulator::display::Pixels) {
-    let mut screen = [['·'; emulator::display::WIDTH as usize]; emulator::display::HEIGHT as usize];
-    pixels.iter().for_each(|p| {
-        screen[p.y as usize][p.x as usize] = '█';
+/// The keys typed in the terminal, read on a thread of their own.
+fn read_keys() -> Receiver<Key> {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        for key in io::stdin().keys() {
+            match key {
+                Ok(key) if sender.send(key).is_ok() => {}
+                _ => break,
+            }
+        }
     });
+    receiver
+}
+
+fn status(meter: &Meter, paused: bool, speed: usize) -> String {
+    format!(
+        " {}  {:.0} fps  {:.0} ips  speed {}  |  ^P {}  ^R reset  Esc quit",
+        if paused { "paused " } else { "running" },
+        meter.fps(),
+        meter.ips(),
+        speed,
+        if paused { "resume" } else { "pause" },
+    )
+}
+
+fn draw<B: tui::backend::Backend + Write>(
+    terminal: &mut Terminal<B>,
+    session: &Session,
+    options: &Options,
+    status: &str,
+) -> Result<()> {
+    let picture = session.picture();
+    let mut image_area = None;
+    terminal.draw(|mut f| {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+            .split(f.size());
+
+        let block = Block::default().borders(Borders::ALL).title(" Chip-8 ");
+        let inner = block.inner(chunks[0]);
+        f.render_widget(block, chunks[0]);
+        f.render_widget(
+            Screen {
+                picture: &picture,
+                palette: &options.palette,
+                output: options.output,
+            },
+            inner,
+        );
+        image_area = Some(inner);
 
-    for row in screen.iter() {
-        for col in row.iter() {
-            print!("{}", col);
+        let text = [Text::raw(status)];
+        let style = Style::default().fg(Color::Black).bg(Color::Gray);
+        f.render_widget(Paragraph::new(text.iter()).style(style), chunks[1]);
+    })?;
+
+    // inline images go straight to the terminal, over the empty pane
+    if let (Output::Graphics(protocol), Some(area)) = (options.output, image_area) {
+        if session.dirty().is_some() {
+            let out = terminal.backend_mut();
+            write!(
+                out,
+                "\x1b[{};{}H{}",
+                area.y + 1,
+                area.x + 1,
+                protocol.encode(&picture.rasterize(&options.palette, options.scale))
+            )?;
+            Write::flush(out)?;
         }
-        print!("\n");
     }
 
-    println!("");
+    Ok(())
 }
@@ -0,0 +1,138 @@
+use crate::emulator::ui::palette::{Palette, Rgb};
+use crate::emulator::ui::render::Picture;
+use crate::emulator::ui::terminal::Output;
+use crate::emulator::ui::text;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::{Color, Style};
+use tui::widgets::Widget;
+
+/// The picture, centred in its pane. Inline images cannot go through the buffer, so for those
+/// the pane is left empty and the image is written at [`centre`] once the frame is drawn.
+pub struct Screen<'a> {
+    pub picture: &'a Picture,
+    pub palette: &'a Palette,
+    pub output: Output,
+}
+
+impl Widget for Screen<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let foreground = self.palette.planes.first().copied().unwrap_or([0xFF; 3]);
+        match self.output {
+            Output::Text(style) => {
+                let text = text::render(self.picture, style);
+                let lines: Vec<&str> = text.lines().collect();
+                let width = lines.first().map_or(0, |l| l.chars().count());
+                let pane = centre(area, width as u16, lines.len() as u16);
+                let colors = Style::default()
+                    .fg(color(foreground))
+                    .bg(color(self.palette.background));
+                for (row, line) in lines.iter().take(pane.height as usize).enumerate() {
+                    buf.set_stringn(
+                        pane.x,
+                        pane.y + row as u16,
+                        line,
+                        pane.width as usize,
+                        colors,
+                    );
+                }
+            }
+            Output::Ansi => {
+                let image = self.picture.rasterize(self.palette, 1);
+                let (width, height) = image.dimensions();
+                let pane = centre(area, width as u16, height.div_ceil(2) as u16);
+                for y in 0..pane.height {
+                    for x in 0..pane.width {
+                        let (px, py) = (x as u32, y as u32 * 2);
+                        let top = image.get_pixel(px, py);
+                        let bottom = image.get_pixel(px, (py + 1).min(height - 1));
+                        buf.get_mut(pane.x + x, pane.y + y)
+                            .set_char('▀')
+                            .set_fg(color([top[0], top[1], top[2]]))
+                            .set_bg(color([bottom[0], bottom[1], bottom[2]]));
+                    }
+                }
+            }
+            Output::Graphics(_) => {}
+        }
+    }
+}
+
+/// The part of `area` a picture of `width` by `height` cells takes up when centred, clipped to
+/// the area.
+pub fn centre(area: Rect, width: u16, height: u16) -> Rect {
+    let (width, height) = (width.min(area.width), height.min(area.height));
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+fn color(rgb: Rgb) -> Color {
+    Color::Rgb(rgb[0], rgb[1], rgb[2])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::ui::text::Style as TextStyle;
+    use crate::emulator::Framebuffer;
+
+    fn picture() -> Picture {
+        let mut fb = Framebuffer::new(4, 4, 1);
+        fb.set(0, 0, 1);
+        fb.set(3, 3, 1);
+        Picture::from(&fb)
+    }
+
+    fn row(buf: &Buffer, y: u16) -> String {
+        (0..buf.area().width)
+            .map(|x| buf.get(x, y).symbol.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_centre() {
+        let area = Rect::new(2, 1, 20, 10);
+        assert_eq!(centre(area, 10, 4), Rect::new(7, 4, 10, 4));
+        assert_eq!(centre(area, 30, 20), area);
+    }
+
+    #[test]
+    fn test_render_text() {
+        let picture = picture();
+        let palette = Palette::default();
+        let mut buf = Buffer::empty(Rect::new(0, 0, 8, 4));
+        let screen = Screen {
+            picture: &picture,
+            palette: &palette,
+            output: Output::Text(TextStyle::HalfBlocks),
+        };
+        screen.render(*buf.area(), &mut buf);
+
+        assert_eq!(row(&buf, 0), "        ");
+        assert_eq!(row(&buf, 1), "  ▀     ");
+        assert_eq!(row(&buf, 2), "     ▄  ");
+        assert_eq!(buf.get(2, 1).style.fg, Color::Rgb(0x00, 0xFF, 0x00));
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        let picture = picture();
+        let palette = Palette::default();
+        let mut buf = Buffer::empty(Rect::new(0, 0, 4, 2));
+        let screen = Screen {
+            picture: &picture,
+            palette: &palette,
+            output: Output::Ansi,
+        };
+        screen.render(*buf.area(), &mut buf);
+
+        assert_eq!(row(&buf, 0), "▀▀▀▀");
+        assert_eq!(buf.get(0, 0).style.fg, Color::Rgb(0x00, 0xFF, 0x00));
+        assert_eq!(buf.get(0, 0).style.bg, Color::Rgb(0x00, 0x00, 0x00));
+        assert_eq!(buf.get(3, 1).style.bg, Color::Rgb(0x00, 0xFF, 0x00));
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Frames and instructions per second, averaged over about the last second.
+#[derive(Debug, Default)]
+pub struct Meter {
+    samples: VecDeque<(Instant, u64, u64)>, // time, frames, instructions
+}
+
+impl Meter {
+    /// Adds the number of frames and instructions run by `now`.
+    pub fn update(&mut self, now: Instant, frames: u64, instructions: u64) {
+        self.samples.push_back((now, frames, instructions));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.rate(|(_, frames, _)| *frames)
+    }
+
+    pub fn ips(&self) -> f64 {
+        self.rate(|(_, _, instructions)| *instructions)
+    }
+
+    fn rate<F: Fn(&(Instant, u64, u64)) -> u64>(&self, count: F) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(first), Some(last)) if last.0 > first.0 => {
+                let elapsed = last.0.duration_since(first.0).as_secs_f64();
+                count(last).saturating_sub(count(first)) as f64 / elapsed
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_meter() {
+        let start = Instant::now();
+        let mut meter = Meter::default();
+        assert_eq!(meter.fps(), 0.0);
+
+        // 60 frames of 10 instructions a second
+        for frame in 0..=120u64 {
+            let now = start + Duration::from_secs(frame) / 60;
+            meter.update(now, frame, frame * 10);
+        }
+        assert!((meter.fps() - 60.0).abs() < 0.01);
+        assert!((meter.ips() - 600.0).abs() < 0.1);
+        assert!(meter.samples.len() <= 62);
+
+        // paused for a second
+        meter.update(start + Duration::from_secs(3), 120, 1200);
+        assert!(meter.fps() < 1.0);
+    }
+}
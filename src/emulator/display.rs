@@ -1,13 +1,25 @@
 use std::fmt;
 
-pub const WIDTH: u8 = 64;
-pub const HEIGHT: u8 = 32;
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+const HIRES_WIDTH: usize = 128;
+const HIRES_HEIGHT: usize = 64;
+
+const MEGA_WIDTH: u16 = 256;
+const MEGA_HEIGHT: u16 = 192;
 
 const SPRITE_WIDTH: u8 = 8;
-const TOTAL_PIXELS: usize = 64 * 32;
+const WIDE_SPRITE_WIDTH: u8 = 16;
+const MEGA_TOTAL_PIXELS: usize = 256 * 192;
+const MAX_PLANES: u8 = 8;
 
 pub type Pixels = Vec<Pixel>;
 
+/// A colour packed as `0xAARRGGBB`, the layout used by MEGA-CHIP palettes.
+pub type Color = u32;
+
+/// A lit pixel. `value` holds one bit per plane the pixel is lit on.
 #[derive(Debug, Clone)]
 pub struct Pixel {
     pub x: u8,
@@ -15,98 +27,620 @@ pub struct Pixel {
     pub value: u8,
 }
 
+/// An area of the screen, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The smallest rectangle covering both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+/// Adds `rect` to an area that may still be empty.
+pub fn extend(area: Option<Rect>, rect: Rect) -> Option<Rect> {
+    Some(area.map_or(rect, |a| a.union(&rect)))
+}
+
+/// A monochrome display of runtime resolution made up of up to eight bitplanes.
+///
+/// Each pixel is stored as a byte with one bit per plane. Drawing, clearing and scrolling only
+/// touch the planes selected by the plane mask.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    planes: u8,
+    plane_mask: u8,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize, planes: u8) -> Self {
+        let planes = planes.clamp(1, MAX_PLANES);
+        Framebuffer {
+            width,
+            height,
+            planes,
+            plane_mask: 1,
+            pixels: vec![0u8; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    /// Selects the planes affected by drawing, clearing and scrolling. Bits for planes that don't
+    /// exist are ignored.
+    pub fn set_plane_mask(&mut self, mask: u8) {
+        self.plane_mask = mask & self.all_planes();
+    }
+
+    fn all_planes(&self) -> u8 {
+        ((1u16 << self.planes) - 1) as u8
+    }
+
+    /// Plane bits of every pixel, row by row.
+    pub fn bits(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Returns the plane bits of the pixel at `(x, y)`, or 0 if it is off screen.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        self.pixels[y * self.width + x] = value & self.all_planes();
+    }
+
+    /// Flips the pixel at `(x, y)` on the selected planes and reports whether any of them was lit.
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let i = y * self.width + x;
+        let collided = self.pixels[i] & self.plane_mask != 0;
+        self.pixels[i] ^= self.plane_mask;
+        collided
+    }
+
+    pub fn clear(&mut self) {
+        let keep = !self.plane_mask;
+        self.pixels.iter_mut().for_each(|p| *p &= keep);
+    }
+
+    /// Changes the resolution. Unless `clear` is set, pixels keep their coordinates: the
+    /// picture stays in the top left corner, and whatever no longer fits is dropped.
+    pub fn resize(&mut self, width: usize, height: usize, clear: bool) {
+        let mut pixels = vec![0u8; width * height];
+        if !clear {
+            let w = width.min(self.width);
+            for y in 0..height.min(self.height) {
+                pixels[y * width..y * width + w]
+                    .copy_from_slice(&self.pixels[y * self.width..y * self.width + w]);
+            }
+        }
+
+        self.width = width;
+        self.height = height;
+        self.pixels = pixels;
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        let n = n.min(self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src = if y + n < self.height {
+                    self.get(x, y + n)
+                } else {
+                    0
+                };
+                self.shift_pixel(x, y, src);
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        let n = n.min(self.height);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let src = if y >= n { self.get(x, y - n) } else { 0 };
+                self.shift_pixel(x, y, src);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        let n = n.min(self.width);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src = if x + n < self.width {
+                    self.get(x + n, y)
+                } else {
+                    0
+                };
+                self.shift_pixel(x, y, src);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        let n = n.min(self.width);
+        for y in 0..self.height {
+            for x in (0..self.width).rev() {
+                let src = if x >= n { self.get(x - n, y) } else { 0 };
+                self.shift_pixel(x, y, src);
+            }
+        }
+    }
+
+    // Moves the selected planes of `src` into the pixel at (x, y), leaving other planes alone.
+    fn shift_pixel(&mut self, x: usize, y: usize, src: u8) {
+        let i = y * self.width + x;
+        self.pixels[i] = (self.pixels[i] & !self.plane_mask) | (src & self.plane_mask);
+    }
+
+    pub fn pixels(&self) -> Pixels {
+        self.pixels
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| {
+                if *value == 0u8 {
+                    return None;
+                }
+
+                let x = (i % self.width) as u8;
+                let y = (i / self.width) as u8;
+                Some(Pixel {
+                    x,
+                    y,
+                    value: *value,
+                })
+            })
+            .collect()
+    }
+}
+
+/// What happens to sprite pixels that run past an edge of the screen.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Edge {
+    Clip,
+    Wrap,
+}
+
 pub struct Sprite {
     x: u8,
     y: u8,
+    width: u8,
     data: Vec<u8>,
 }
 
 impl Sprite {
     pub fn new(x: u8, y: u8, data: Vec<u8>) -> Self {
-        Sprite { x, y, data }
+        Sprite {
+            x,
+            y,
+            width: SPRITE_WIDTH,
+            data,
+        }
     }
+
+    /// A 16 pixel wide SUPER-CHIP sprite, stored as two bytes per row.
+    pub fn wide(x: u8, y: u8, data: Vec<u8>) -> Self {
+        Sprite {
+            x,
+            y,
+            width: WIDE_SPRITE_WIDTH,
+            data,
+        }
+    }
+
+    fn rows(&self) -> Vec<u16> {
+        if self.width == WIDE_SPRITE_WIDTH {
+            self.data
+                .chunks(2)
+                .map(|c| ((c[0] as u16) << 8) | *c.get(1).unwrap_or(&0) as u16)
+                .collect()
+        } else {
+            self.data.iter().map(|v| (*v as u16) << 8).collect()
+        }
+    }
+}
+
+/// The result of drawing a sprite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Drawn {
+    /// The value for VF.
+    pub flag: u8,
+    /// Bounding rectangle of the pixels that changed, if any did.
+    pub dirty: Option<Rect>,
 }
 
+/// The CHIP-8 and SUPER-CHIP display: sprite drawing on top of a [`Framebuffer`].
+///
+/// Sprite coordinates always wrap around the screen. Whether the pixels of a sprite that
+/// crosses an edge are clipped or wrapped is chosen separately for each axis.
 pub struct Screen {
-    pixels: [u8; TOTAL_PIXELS],
+    framebuffer: Framebuffer,
+    horizontal: Edge,
+    vertical: Edge,
 }
 
 impl Default for Screen {
     fn default() -> Self {
         Screen {
-            pixels: [0u8; TOTAL_PIXELS],
+            framebuffer: Framebuffer::new(WIDTH, HEIGHT, 1),
+            horizontal: Edge::Clip,
+            vertical: Edge::Clip,
         }
     }
 }
 
 impl Screen {
     pub fn clear(&mut self) {
-        self.pixels.iter_mut().for_each(|p| *p = 0u8);
+        self.framebuffer.clear();
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        &mut self.framebuffer
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.framebuffer.width() == HIRES_WIDTH
+    }
+
+    pub fn set_edges(&mut self, horizontal: Edge, vertical: Edge) {
+        self.horizontal = horizontal;
+        self.vertical = vertical;
     }
 
-    pub fn draw(&mut self, sprite: Sprite) -> Option<u8> {
-        if sprite.x >= WIDTH || sprite.y >= HEIGHT {
-            return None;
+    /// Switches between the 64x32 and 128x64 resolutions. SUPER-CHIP 1.x leaves the display
+    /// buffer alone, so unless `clear` is set the pixels stay where they are instead of being
+    /// scaled to the new resolution.
+    pub fn set_hires(&mut self, hires: bool, clear: bool) {
+        if hires == self.is_hires() {
+            if clear {
+                self.clear();
+            }
+            return;
         }
 
-        let mut vf = 0;
-        let width = if sprite.x >= (WIDTH - SPRITE_WIDTH) {
-            WIDTH - sprite.x
+        if hires {
+            self.framebuffer.resize(HIRES_WIDTH, HIRES_HEIGHT, clear);
         } else {
-            SPRITE_WIDTH
-        };
+            self.framebuffer.resize(WIDTH, HEIGHT, clear);
+        }
+    }
+
+    pub fn draw(&mut self, sprite: Sprite) -> Option<Drawn> {
+        self.blit(sprite).map(|(collided, _, dirty)| Drawn {
+            flag: if collided > 0 { 1 } else { 0 },
+            dirty,
+        })
+    }
+
+    /// Draws the sprite and sets the flag to the number of rows that either collided or were
+    /// clipped at the bottom of the screen, as SUPER-CHIP 1.1 does in high resolution.
+    pub fn draw_counting_rows(&mut self, sprite: Sprite) -> Option<Drawn> {
+        self.blit(sprite).map(|(collided, clipped, dirty)| Drawn {
+            flag: collided + clipped,
+            dirty,
+        })
+    }
+
+    /// The whole screen at its current resolution.
+    pub fn area(&self) -> Rect {
+        Rect::new(0, 0, self.framebuffer.width(), self.framebuffer.height())
+    }
 
-        for (h, v) in sprite.data.iter().enumerate() {
-            for w in 0..width {
-                let sprite_pixel = v & (0x80 >> w);
-                if sprite_pixel != 0 {
-                    let index = Screen::calc_index(sprite.x, sprite.y, w, h as u8);
-                    if index >= self.pixels.len() {
-                        return Some(vf);
+    fn blit(&mut self, sprite: Sprite) -> Option<(u8, u8, Option<Rect>)> {
+        let screen_width = self.framebuffer.width();
+        let screen_height = self.framebuffer.height();
+        let x = sprite.x as usize % screen_width;
+        let y = sprite.y as usize % screen_height;
+
+        let changes = self.framebuffer.plane_mask() != 0;
+        let mut dirty = None;
+        let mut collided = 0;
+        let mut clipped = 0;
+        for (h, v) in sprite.rows().iter().enumerate() {
+            let mut py = y + h;
+            if py >= screen_height {
+                match self.vertical {
+                    Edge::Clip => {
+                        clipped += 1;
+                        continue;
                     }
+                    Edge::Wrap => py %= screen_height,
+                }
+            }
 
-                    if self.pixels[index] == 1 {
-                        vf = 1;
+            let mut row_collided = false;
+            for w in 0..sprite.width as usize {
+                if v & (0x8000 >> w) == 0 {
+                    continue;
+                }
+
+                let mut px = x + w;
+                if px >= screen_width {
+                    match self.horizontal {
+                        Edge::Clip => break,
+                        Edge::Wrap => px %= screen_width,
                     }
-                    self.pixels[index] ^= 1;
+                }
+
+                if self.framebuffer.toggle(px, py) {
+                    row_collided = true;
+                }
+                if changes {
+                    dirty = extend(dirty, Rect::new(px, py, 1, 1));
                 }
             }
+
+            if row_collided {
+                collided += 1;
+            }
         }
 
-        Some(vf)
+        Some((collided, clipped, dirty))
     }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    Percent25,
+    Percent50,
+    Additive,
+    Multiply,
+}
 
-    fn calc_index(base_x: u8, base_y: u8, x: u8, y: u8) -> usize {
-        let y_offset = (base_y + y) as u64 * WIDTH as u64;
-        let x_offset = (base_x + x) as u64;
-        (x_offset + y_offset) as usize
+impl From<u8> for BlendMode {
+    fn from(mode: u8) -> Self {
+        match mode {
+            1 => BlendMode::Percent25,
+            2 => BlendMode::Percent50,
+            3 => BlendMode::Additive,
+            4 => BlendMode::Multiply,
+            _ => BlendMode::Normal,
+        }
     }
+}
 
-    pub fn pixels(&self) -> Pixels {
-        self.pixels
-            .iter()
-            .enumerate()
-            .filter_map(|(i, value)| {
-                if *value == 0u8 {
-                    return None;
+impl BlendMode {
+    fn blend(self, src: Color, dst: Color) -> Color {
+        let mix = |shift: u32| {
+            let s = (src >> shift) & 0xFF;
+            let d = (dst >> shift) & 0xFF;
+            let v = match self {
+                BlendMode::Normal => s,
+                BlendMode::Percent25 => (s + d * 3) / 4,
+                BlendMode::Percent50 => (s + d) / 2,
+                BlendMode::Additive => (s + d).min(0xFF),
+                BlendMode::Multiply => (s * d) / 0xFF,
+            };
+            v << shift
+        };
+
+        0xFF00_0000 | mix(16) | mix(8) | mix(0)
+    }
+}
+
+/// A MEGA-CHIP sprite: one palette index per pixel, with index 0 being transparent.
+pub struct ColorSprite {
+    x: u8,
+    y: u8,
+    width: usize,
+    data: Vec<u8>,
+}
+
+impl ColorSprite {
+    pub fn new(x: u8, y: u8, width: usize, data: Vec<u8>) -> Self {
+        ColorSprite { x, y, width, data }
+    }
+}
+
+/// A snapshot of the MEGA-CHIP display, ready to be rendered.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u16,
+    pub height: u16,
+    pub alpha: u8,
+    pub colors: Vec<Color>,
+}
+
+/// The 256x192 indexed-colour display used in MEGA-CHIP mode.
+///
+/// Palette indices are kept alongside the blended colours because collisions are
+/// detected against the index that was last drawn on a pixel.
+pub struct ColorScreen {
+    indices: Vec<u8>,
+    colors: Vec<Color>,
+    palette: [Color; 256],
+    sprite_width: u8,
+    sprite_height: u8,
+    blend_mode: BlendMode,
+    alpha: u8,
+    collision_index: u8,
+}
+
+impl Default for ColorScreen {
+    fn default() -> Self {
+        ColorScreen {
+            indices: vec![0u8; MEGA_TOTAL_PIXELS],
+            colors: vec![0; MEGA_TOTAL_PIXELS],
+            palette: [0; 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            blend_mode: BlendMode::Normal,
+            alpha: 0xFF,
+            collision_index: 0,
+        }
+    }
+}
+
+impl ColorScreen {
+    pub fn area(&self) -> Rect {
+        Rect::new(0, 0, MEGA_WIDTH as usize, MEGA_HEIGHT as usize)
+    }
+
+    pub fn clear(&mut self) {
+        self.indices.iter_mut().for_each(|p| *p = 0u8);
+        self.colors.iter_mut().for_each(|p| *p = 0);
+    }
+
+    /// Loads `colors` into the palette starting at index 1. Index 0 is always transparent.
+    pub fn load_palette(&mut self, colors: &[Color]) {
+        self.palette[1..]
+            .iter_mut()
+            .zip(colors.iter())
+            .for_each(|(p, c)| *p = *c);
+    }
+
+    pub fn set_sprite_width(&mut self, width: u8) {
+        self.sprite_width = width;
+    }
+
+    pub fn set_sprite_height(&mut self, height: u8) {
+        self.sprite_height = height;
+    }
+
+    // A sprite dimension of zero means 256 pixels.
+    pub fn sprite_size(&self) -> (usize, usize) {
+        let dim = |v: u8| if v == 0 { 256 } else { v as usize };
+        (dim(self.sprite_width), dim(self.sprite_height))
+    }
+
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    pub fn set_alpha(&mut self, alpha: u8) {
+        self.alpha = alpha;
+    }
+
+    pub fn set_collision_index(&mut self, index: u8) {
+        self.collision_index = index;
+    }
+
+    pub fn draw(&mut self, sprite: ColorSprite) -> u8 {
+        let mut vf = 0;
+        if sprite.width == 0 {
+            return vf;
+        }
+
+        for (h, row) in sprite.data.chunks(sprite.width).enumerate() {
+            let y = sprite.y as usize + h;
+            if y >= MEGA_HEIGHT as usize {
+                break;
+            }
+
+            for (w, index) in row.iter().enumerate() {
+                let x = sprite.x as usize + w;
+                if x >= MEGA_WIDTH as usize {
+                    break;
                 }
 
-                let x = (i as u64 % WIDTH as u64) as u8;
-                let y = (i as u64 / WIDTH as u64) as u8;
-                Some(Pixel {
-                    x,
-                    y,
-                    value: *value,
-                })
-            })
-            .collect()
+                if *index == 0 {
+                    continue;
+                }
+
+                let i = y * MEGA_WIDTH as usize + x;
+                if self.indices[i] == self.collision_index && self.indices[i] != 0 {
+                    vf = 1;
+                }
+
+                self.indices[i] = *index;
+                self.colors[i] = self
+                    .blend_mode
+                    .blend(self.palette[*index as usize], self.colors[i]);
+            }
+        }
+
+        vf
+    }
+
+    /// Moves the picture `dx` pixels right and `dy` down, leaving transparent black behind.
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (MEGA_WIDTH as isize, MEGA_HEIGHT as isize);
+        let indices = self.indices.clone();
+        let colors = self.colors.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let i = (y * width + x) as usize;
+                if sx >= 0 && sy >= 0 && sx < width && sy < height {
+                    let src = (sy * width + sx) as usize;
+                    self.indices[i] = indices[src];
+                    self.colors[i] = colors[src];
+                } else {
+                    self.indices[i] = 0;
+                    self.colors[i] = 0;
+                }
+            }
+        }
+    }
+
+    pub fn frame(&self) -> Frame {
+        Frame {
+            width: MEGA_WIDTH,
+            height: MEGA_HEIGHT,
+            alpha: self.alpha,
+            colors: self.colors.clone(),
+        }
     }
 }
 
-impl fmt::Display for Screen {
+impl fmt::Display for Framebuffer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for (i, v) in self.pixels.iter().enumerate() {
-            if i % WIDTH as usize == 0 {
+            if i % self.width == 0 {
                 write!(f, "\n")?;
             }
             let symbol = if *v == 0 { "·" } else { "█" };
@@ -116,6 +650,12 @@ impl fmt::Display for Screen {
     }
 }
 
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.framebuffer.fmt(f)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -124,10 +664,295 @@ mod test {
     fn test_draw_sprite() {
         let mut scr = Screen::default();
         let result = scr.draw(Sprite::new(10, 10, vec![0xF0, 0x90, 0xF0, 0x10, 0xF0]));
-        assert_eq!(result, Some(0));
+        assert_eq!(result.map(|d| d.flag), Some(0));
         println!("{}", scr);
 
         let result = scr.draw(Sprite::new(10, 10, vec![0xF0, 0x90, 0xF0, 0x10, 0xF0]));
-        assert_eq!(result, Some(1));
+        assert_eq!(result.map(|d| d.flag), Some(1));
+    }
+
+    fn draw_block(x: u8, y: u8, horizontal: Edge, vertical: Edge) -> Vec<(u8, u8)> {
+        let mut scr = Screen::default();
+        scr.set_edges(horizontal, vertical);
+        scr.draw(Sprite::new(x, y, vec![0xC0, 0xC0]));
+        scr.framebuffer()
+            .pixels()
+            .iter()
+            .map(|p| (p.x, p.y))
+            .collect()
+    }
+
+    macro_rules! test_draw_edges {
+        ($name:ident, ($x:expr, $y:expr), $horizontal:expr, $vertical:expr, $want:expr) => {
+            #[test]
+            fn $name() {
+                let want: Vec<(u8, u8)> = $want.to_vec();
+                assert_eq!(want, draw_block($x, $y, $horizontal, $vertical));
+            }
+        };
+    }
+
+    use Edge::{Clip, Wrap};
+
+    test_draw_edges!(
+        test_edges_inside,
+        (10, 10),
+        Clip,
+        Clip,
+        [(10, 10), (11, 10), (10, 11), (11, 11)]
+    );
+    test_draw_edges!(
+        test_edges_origin,
+        (0, 0),
+        Wrap,
+        Wrap,
+        [(0, 0), (1, 0), (0, 1), (1, 1)]
+    );
+    test_draw_edges!(
+        test_edges_right_clip,
+        (63, 10),
+        Clip,
+        Clip,
+        [(63, 10), (63, 11)]
+    );
+    test_draw_edges!(
+        test_edges_right_wrap,
+        (63, 10),
+        Wrap,
+        Clip,
+        [(0, 10), (63, 10), (0, 11), (63, 11)]
+    );
+    test_draw_edges!(
+        test_edges_bottom_clip,
+        (10, 31),
+        Clip,
+        Clip,
+        [(10, 31), (11, 31)]
+    );
+    test_draw_edges!(
+        test_edges_bottom_wrap,
+        (10, 31),
+        Clip,
+        Wrap,
+        [(10, 0), (11, 0), (10, 31), (11, 31)]
+    );
+    test_draw_edges!(test_edges_corner_clip, (63, 31), Clip, Clip, [(63, 31)]);
+    test_draw_edges!(
+        test_edges_corner_wrap_x,
+        (63, 31),
+        Wrap,
+        Clip,
+        [(0, 31), (63, 31)]
+    );
+    test_draw_edges!(
+        test_edges_corner_wrap_y,
+        (63, 31),
+        Clip,
+        Wrap,
+        [(63, 0), (63, 31)]
+    );
+    test_draw_edges!(
+        test_edges_corner_wrap,
+        (63, 31),
+        Wrap,
+        Wrap,
+        [(0, 0), (63, 0), (0, 31), (63, 31)]
+    );
+    test_draw_edges!(
+        test_edges_start_wraps_x,
+        (74, 10),
+        Clip,
+        Clip,
+        [(10, 10), (11, 10), (10, 11), (11, 11)]
+    );
+    test_draw_edges!(
+        test_edges_start_wraps_y,
+        (10, 37),
+        Clip,
+        Clip,
+        [(10, 5), (11, 5), (10, 6), (11, 6)]
+    );
+    test_draw_edges!(
+        test_edges_start_wraps_max,
+        (255, 255),
+        Clip,
+        Clip,
+        [(63, 31)]
+    );
+    test_draw_edges!(
+        test_edges_start_exact_width,
+        (64, 32),
+        Clip,
+        Clip,
+        [(0, 0), (1, 0), (0, 1), (1, 1)]
+    );
+
+    #[test]
+    fn test_draw_counts_clipped_rows() {
+        let mut scr = Screen::default();
+        scr.set_edges(Edge::Clip, Edge::Clip);
+        let result = scr.draw_counting_rows(Sprite::new(0, 30, vec![0x00, 0x00, 0x80, 0x80]));
+        assert_eq!(
+            result,
+            Some(Drawn {
+                flag: 2,
+                dirty: None
+            })
+        );
+        assert!(scr.framebuffer().pixels().is_empty());
+    }
+
+    #[test]
+    fn test_draw_wide_sprite_counting_rows() {
+        let mut scr = Screen::default();
+        scr.set_hires(true, true);
+
+        let data = vec![0xFF; 32];
+        let result = scr.draw_counting_rows(Sprite::wide(120, 56, data.clone()));
+        assert_eq!(result.map(|d| d.flag), Some(8));
+
+        let result = scr.draw_counting_rows(Sprite::wide(120, 0, data));
+        assert_eq!(result.map(|d| d.flag), Some(0));
+        assert_eq!(scr.framebuffer().pixels().len(), 8 * 8 + 8 * 16);
+    }
+
+    #[test]
+    fn test_draw_dirty() {
+        let mut scr = Screen::default();
+        let result = scr.draw(Sprite::new(10, 4, vec![0x18, 0x00, 0x81]));
+        assert_eq!(result.unwrap().dirty, Some(Rect::new(10, 4, 8, 3)));
+
+        scr.set_edges(Edge::Wrap, Edge::Clip);
+        let result = scr.draw(Sprite::new(62, 0, vec![0xF0]));
+        assert_eq!(result.unwrap().dirty, Some(Rect::new(0, 0, 64, 1)));
+
+        scr.framebuffer_mut().set_plane_mask(0);
+        let result = scr.draw(Sprite::new(0, 0, vec![0xFF]));
+        assert_eq!(result.unwrap().dirty, None);
+    }
+
+    #[test]
+    fn test_rect_union() {
+        let a = Rect::new(2, 3, 4, 1);
+        let b = Rect::new(5, 0, 1, 2);
+        assert_eq!(a.union(&b), Rect::new(2, 0, 4, 4));
+        assert_eq!(extend(None, a), Some(a));
+        assert!(a.contains(5, 3));
+        assert!(!a.contains(6, 3));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut fb = Framebuffer::new(64, 32, 1);
+        fb.set(0, 0, 1);
+
+        fb.scroll_down(2);
+        fb.scroll_right(4);
+        let pixels = fb.pixels();
+        assert_eq!(pixels.len(), 1);
+        assert_eq!((pixels[0].x, pixels[0].y), (4, 2));
+
+        fb.scroll_up(1);
+        fb.scroll_left(3);
+        assert_eq!(fb.get(1, 1), 1);
+
+        fb.scroll_left(8);
+        assert!(fb.pixels().is_empty());
+    }
+
+    #[test]
+    fn test_plane_mask() {
+        let mut fb = Framebuffer::new(16, 8, 2);
+        fb.set(0, 0, 0b11);
+        fb.set(1, 0, 0b01);
+
+        fb.set_plane_mask(0b10);
+        assert!(fb.toggle(0, 0));
+        assert!(!fb.toggle(1, 0));
+        assert_eq!(fb.get(0, 0), 0b01);
+        assert_eq!(fb.get(1, 0), 0b11);
+
+        fb.scroll_right(1);
+        assert_eq!(fb.get(1, 0), 0b01);
+        assert_eq!(fb.get(2, 0), 0b10);
+
+        fb.clear();
+        assert_eq!(fb.get(1, 0), 0b01);
+        assert_eq!(fb.get(2, 0), 0);
+
+        fb.set_plane_mask(0xFF);
+        assert_eq!(fb.plane_mask(), 0b11);
+    }
+
+    #[test]
+    fn test_set_hires_keeps_contents() {
+        let mut scr = Screen::default();
+        scr.draw(Sprite::new(1, 1, vec![0x80]));
+        scr.draw(Sprite::new(63, 31, vec![0x80]));
+
+        scr.set_hires(true, false);
+        let pixels = scr.framebuffer().pixels();
+        assert_eq!(pixels.len(), 2);
+        assert_eq!((pixels[0].x, pixels[0].y), (1, 1));
+        assert_eq!((pixels[1].x, pixels[1].y), (63, 31));
+        assert_eq!(scr.framebuffer().width(), 128);
+
+        // going back to low resolution drops what is off screen
+        scr.draw(Sprite::new(100, 40, vec![0x80]));
+        scr.set_hires(false, false);
+        assert_eq!(scr.framebuffer().pixels().len(), 2);
+        assert_eq!(scr.framebuffer().width(), 64);
+
+        scr.set_hires(true, true);
+        assert!(scr.framebuffer().pixels().is_empty());
+        assert_eq!(scr.framebuffer().height(), 64);
+    }
+
+    #[test]
+    fn test_draw_color_sprite() {
+        let mut scr = ColorScreen::default();
+        scr.load_palette(&[0xFFFF_0000, 0xFF00_00FF]);
+        scr.set_collision_index(1);
+
+        let result = scr.draw(ColorSprite::new(254, 10, 3, vec![1, 0, 1, 2, 2, 2]));
+        assert_eq!(result, 0);
+
+        let frame = scr.frame();
+        let at = |x: usize, y: usize| frame.colors[y * MEGA_WIDTH as usize + x];
+        assert_eq!(at(254, 10), 0xFFFF_0000);
+        assert_eq!(at(255, 10), 0);
+        assert_eq!(at(254, 11), 0xFF00_00FF);
+        assert_eq!(at(255, 11), 0xFF00_00FF);
+
+        let result = scr.draw(ColorSprite::new(254, 10, 1, vec![2]));
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn test_scroll_color() {
+        let mut scr = ColorScreen::default();
+        scr.load_palette(&[0xFFFF_0000]);
+        scr.draw(ColorSprite::new(0, 0, 2, vec![1, 1]));
+
+        scr.scroll(4, 2);
+        let at = |scr: &ColorScreen, x: usize, y: usize| scr.colors[y * MEGA_WIDTH as usize + x];
+        assert_eq!(at(&scr, 0, 0), 0);
+        assert_eq!(at(&scr, 4, 2), 0xFFFF_0000);
+        assert_eq!(at(&scr, 5, 2), 0xFFFF_0000);
+
+        scr.scroll(-5, 0);
+        assert_eq!(at(&scr, 0, 2), 0xFFFF_0000);
+        assert_eq!(at(&scr, 1, 2), 0);
+        assert_eq!(scr.indices.iter().filter(|&&i| i != 0).count(), 1);
+    }
+
+    #[test]
+    fn test_blend_modes() {
+        let src = 0xFF80_4020;
+        let dst = 0xFF40_8040;
+        assert_eq!(BlendMode::Normal.blend(src, dst), src);
+        assert_eq!(BlendMode::Percent50.blend(src, dst), 0xFF60_6030);
+        assert_eq!(BlendMode::Additive.blend(src, dst), 0xFFC0_C060);
+        assert_eq!(BlendMode::Multiply.blend(src, dst), 0xFF20_2008);
     }
 }
@@ -1,12 +1,15 @@
 use super::common::{Error, Result};
 use super::display;
 use super::interpreter::*;
-use log::debug;
-use rand::Rng;
+use super::keypad::Keypad;
+use super::platform::{IndexIncrement, LoresDxy0, Platform, Quirks};
+use super::storage;
+use log::{debug, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::io::Read;
 
 const REG_COUNT: usize = 16;
-const MEM_SIZE: usize = 4096;
 const MEM_START: usize = 512;
 const STACK_SIZE: usize = 16;
 
@@ -29,15 +32,55 @@ const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, //F
 ];
 
+const BIG_FONT_START: usize = 80;
+const BIG_FONT_SET: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, //0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, //1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, //2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, //3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, //4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, //5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, //6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, //7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, //8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, //9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, //A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, //B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, //C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, //D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, //E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, //F
+];
+
+/// The 4x5 sprite of a hex digit in the built-in font, one byte per row with the pixels in the
+/// high nibble.
+pub fn font_sprite(digit: u8) -> &'static [u8] {
+    let start = (digit & 0xF) as usize * 5;
+    &FONT_SET[start..start + 5]
+}
+
 pub type StepResult = Result<Option<Step>>;
 
 pub enum Step {
     Nop,
-    Draw(display::Pixels),
+    Draw(display::Framebuffer),
+    DrawColor(display::Frame),
+    PlaySample(Sample),
+    StopSample,
+    SoundOn,  // the sound timer started
+    SoundOff, // the sound timer ran out or was cleared
     WaitForKey,
     Exit,
 }
 
+/// Digitised sound started by the MEGA-CHIP `DIGISND` instruction.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub rate: u16,
+    pub data: Vec<u8>,
+    pub looping: bool,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Input {
     Key0,
@@ -58,42 +101,186 @@ pub enum Input {
     KeyF,
 }
 
+/// The CHIP-8 keys in hex order.
+pub const KEYS: [Input; 16] = [
+    Input::Key0,
+    Input::Key1,
+    Input::Key2,
+    Input::Key3,
+    Input::Key4,
+    Input::Key5,
+    Input::Key6,
+    Input::Key7,
+    Input::Key8,
+    Input::Key9,
+    Input::KeyA,
+    Input::KeyB,
+    Input::KeyC,
+    Input::KeyD,
+    Input::KeyE,
+    Input::KeyF,
+];
+
+/// Parses a hex digit into the CHIP-8 key with that value.
+pub fn parse_key(digit: &str) -> Option<Input> {
+    match u8::from_str_radix(digit, 16) {
+        Ok(n) if digit.len() == 1 => Some(KEYS[n as usize]),
+        _ => None,
+    }
+}
+
+/// A CHIP-8 interpreter. `step` runs one instruction. Frontends have to call `start_frame` and
+/// `tick_timers` before each 60 Hz frame's instructions, no matter how many the frame runs: the
+/// keypad and the delay and sound timers go by frames, not instructions.
 pub struct Emulator {
     vx: [u8; REG_COUNT], // V0 - VF registers
     dt: u8,              // delay timer
     st: u8,              // sound timer
     sp: u8,              // stack pointer
-    i: u16,              // I
+    i: usize,            // I
     pc: usize,           // program counter
-    memory: [u8; MEM_SIZE],
+    memory: Vec<u8>,
     stack: [usize; STACK_SIZE],
     screen: display::Screen,
-    keyboard: [bool; 16],
+    color_screen: Option<display::ColorScreen>, // present while MEGA-CHIP mode is on
+    keypad: Keypad,
+    cycles: u64,  // instructions run
+    rom: Vec<u8>, // as loaded, for reset
     rom_end: usize,
+    rom_hash: String,
+    platform: Platform,
+    quirks: Quirks,
+    rpl: [u8; storage::RPL_COUNT], // SUPER-CHIP user flags
+    flag_store: Option<storage::FlagStore>,
+    rng: StdRng,
+    dirty: Option<display::Rect>, // changed since the last take_dirty
 }
 
 impl Emulator {
     pub fn new<R: Read>(rom_data: R) -> Result<Emulator> {
+        Emulator::with_platform(rom_data, Platform::default())
+    }
+
+    pub fn with_platform<R: Read>(rom_data: R, platform: Platform) -> Result<Emulator> {
         let mut emu = Emulator {
             vx: [0u8; REG_COUNT],
             dt: 0u8,
             st: 0u8,
             sp: 0u8,
-            i: 0u16,
+            i: 0,
             pc: MEM_START,
-            memory: [0u8; MEM_SIZE],
+            memory: vec![0u8; platform.memory_size()],
             stack: [0; STACK_SIZE],
             screen: display::Screen::default(),
-            keyboard: [false; 16],
+            color_screen: None,
+            keypad: Keypad::default(),
+            cycles: 0,
+            rom: Vec::new(),
             rom_end: 0,
+            rom_hash: String::new(),
+            platform,
+            quirks: platform.quirks(),
+            rpl: [0u8; storage::RPL_COUNT],
+            flag_store: None,
+            rng: StdRng::from_entropy(),
+            dirty: None,
         };
 
+        emu.set_quirks(platform.quirks());
         emu.memory[..80].copy_from_slice(&FONT_SET[..]);
+        emu.memory[BIG_FONT_START..BIG_FONT_START + 160].copy_from_slice(&BIG_FONT_SET[..]);
         emu.load_rom(rom_data)?;
 
         Ok(emu)
     }
 
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Overrides the quirks of the platform the emulator was created for.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.screen
+            .set_edges(quirks.horizontal_edge, quirks.vertical_edge);
+    }
+
+    /// Makes `RND` produce the same sequence on every run.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn framebuffer(&self) -> &display::Framebuffer {
+        self.screen.framebuffer()
+    }
+
+    /// The area of the screen that changed since the last call, in the current resolution.
+    /// Frontends call this at the end of every frame to repaint only what is needed.
+    pub fn take_dirty(&mut self) -> Option<display::Rect> {
+        self.dirty.take()
+    }
+
+    /// Starts the program again from the beginning, as if it had just been loaded. The quirks,
+    /// the user flags and the random number generator are kept.
+    pub fn reset(&mut self) {
+        self.vx = [0u8; REG_COUNT];
+        self.dt = 0;
+        self.st = 0;
+        self.sp = 0;
+        self.i = 0;
+        self.pc = MEM_START;
+        self.stack = [0; STACK_SIZE];
+        self.screen = display::Screen::default();
+        self.color_screen = None;
+        self.keypad = Keypad::default();
+        self.set_quirks(self.quirks);
+
+        let rom = std::mem::take(&mut self.rom);
+        self.unload_rom();
+        self.memory[MEM_START..MEM_START + rom.len()].copy_from_slice(&rom);
+        self.rom_end = MEM_START + rom.len();
+        self.rom = rom;
+        self.mark_screen_dirty();
+    }
+
+    /// The number of instructions run so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// SHA-1 digest of the loaded ROM.
+    pub fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    /// Keeps the RPL user flags in `store` from now on, loading any flags it holds. Until a
+    /// store is set, the flags start out cleared and are lost with the emulator.
+    pub fn set_flag_store(&mut self, store: storage::FlagStore) {
+        self.load_flags(Some(store));
+    }
+
+    /// Clears the SUPER-CHIP user flags and stops saving them, so that the run does not depend
+    /// on earlier sessions.
+    pub fn forget_flags(&mut self) {
+        self.load_flags(None);
+    }
+
+    fn load_flags(&mut self, store: Option<storage::FlagStore>) {
+        self.rpl = [0u8; storage::RPL_COUNT];
+        if let Some(s) = store.as_ref() {
+            match s.load() {
+                Ok(flags) => self.rpl = flags,
+                Err(err) => warn!("Failed to load RPL flags: {}", err),
+            }
+        }
+
+        self.flag_store = store;
+    }
+
     fn load_rom<R: Read>(&mut self, rom_data: R) -> Result<()> {
         self.unload_rom();
         debug!("Loading ROM");
@@ -103,18 +290,20 @@ impl Emulator {
             self.memory[i + MEM_START] = byte?;
 
             i += 1;
-            if i + MEM_START >= MEM_SIZE {
+            if i + MEM_START >= self.memory.len() {
                 return Err(Error::InvalidROM);
             }
         }
 
         self.rom_end = i + MEM_START;
+        self.rom = self.memory[MEM_START..self.rom_end].to_vec();
+        self.rom_hash = storage::rom_hash(&self.rom);
 
         Ok(())
     }
 
     fn unload_rom(&mut self) {
-        for i in MEM_START..MEM_SIZE {
+        for i in MEM_START..self.memory.len() {
             self.memory[i] = 0u8;
         }
 
@@ -132,46 +321,83 @@ impl Emulator {
         Some(Instruction(ins))
     }
 
-    pub fn step(&mut self) -> StepResult {
-        // decrease delay timer
+    /// Counts the delay and sound timers down. Call once per 60 Hz frame, before the frame's
+    /// instructions. Returns `Step::SoundOff` on the tick that runs the sound timer out.
+    pub fn tick_timers(&mut self) -> Option<Step> {
         if self.dt > 0 {
             self.dt -= 1;
         }
 
-        // decrease sound timer
         if self.st > 0 {
             self.st -= 1;
+            if self.st == 0 {
+                return Some(Step::SoundOff);
+            }
         }
 
+        None
+    }
+
+    /// Starts a 60 Hz frame for the keypad: keys tapped during the last one can go up now, and
+    /// [`Keypad::polled`] reports the keys it looked at. Call once per frame, before its
+    /// instructions.
+    pub fn start_frame(&mut self) {
+        self.keypad.end_frame(self.cycles);
+    }
+
+    /// Runs one instruction. Timers are left alone; see `tick_timers`.
+    pub fn step(&mut self) -> StepResult {
+        self.keypad.apply(self.cycles);
+        self.cycles += 1;
         match self.next_instruction() {
             Some(ins) => {
-                let op = ins.interpret()?;
+                let op = match self.platform {
+                    Platform::MegaChip => ins.interpret_megachip()?,
+                    Platform::Chip8 => ins.interpret()?,
+                    _ => ins.interpret_schip()?,
+                };
                 debug!("EXEC:\t{}\t{}", ins, op);
 
                 match op {
                     Op::ADD(reg, val) => self.do_add(reg, val),
                     Op::ADDI(reg) => self.do_addi(reg),
                     Op::ADDR(reg1, reg2) => self.do_addr(reg1, reg2),
+                    Op::ALPHA(val) => self.do_alpha(val),
                     Op::AND(reg1, reg2) => self.do_and(reg1, reg2),
+                    Op::BMODE(val) => self.do_bmode(val),
                     Op::CALL(addr) => self.do_call(addr),
+                    Op::CCOL(val) => self.do_ccol(val),
                     Op::CLS => self.do_cls(),
                     Op::CPDT(reg) => self.do_cpdt(reg),
+                    Op::DIGISND(val) => self.do_digisnd(val),
                     Op::DRW(reg1, reg2, val) => self.do_drw(reg1, reg2, val),
+                    Op::EXIT => Ok(Some(Step::Exit)),
+                    Op::HIGH => self.do_high(),
                     Op::JP(addr) => self.do_jp(addr),
                     Op::JPREL(addr) => self.do_jprel(addr),
                     Op::LD(reg, val) => self.do_ld(reg, val),
                     Op::LDDT(reg) => self.do_lddt(reg),
+                    Op::LDHF(reg) => self.do_ldhf(reg),
+                    Op::LDHI(val) => self.do_ldhi(val),
                     Op::LDI(addr) => self.do_ldi(addr),
                     Op::LDIB(reg) => self.do_ldib(reg),
                     Op::LDIM(reg) => self.do_ldim(reg),
                     Op::LDIR(reg) => self.do_ldir(reg),
                     Op::LDIS(reg) => self.do_ldis(reg),
                     Op::LDKP(reg) => self.do_ldkp(reg),
+                    Op::LDPAL(val) => self.do_ldpal(val),
                     Op::LDR(reg1, reg2) => self.do_ldr(reg1, reg2),
+                    Op::LDRPL(reg) => self.do_ldrpl(reg),
                     Op::LDST(reg) => self.do_ldst(reg),
+                    Op::LOW => self.do_low(),
+                    Op::MEGAOFF => self.do_megaoff(),
+                    Op::MEGAON => self.do_megaon(),
                     Op::OR(reg1, reg2) => self.do_or(reg1, reg2),
                     Op::RET => self.do_ret(),
                     Op::RND(reg, val) => self.do_rnd(reg, val),
+                    Op::SCD(val) => self.do_scd(val),
+                    Op::SCL => self.do_scl(),
+                    Op::SCR => self.do_scr(),
                     Op::SE(reg, val) => self.do_se(reg, val),
                     Op::SER(reg1, reg2) => self.do_ser(reg1, reg2),
                     Op::SHL(reg) => self.do_shl(reg),
@@ -180,6 +406,10 @@ impl Emulator {
                     Op::SKP(reg) => self.do_skp(reg),
                     Op::SNE(reg, val) => self.do_sne(reg, val),
                     Op::SNER(reg1, reg2) => self.do_sner(reg1, reg2),
+                    Op::SPRH(val) => self.do_sprh(val),
+                    Op::SPRW(val) => self.do_sprw(val),
+                    Op::STOPSND => self.do_stopsnd(),
+                    Op::STRPL(reg) => self.do_strpl(reg),
                     Op::SUB(reg1, reg2) => self.do_sub(reg1, reg2),
                     Op::SUBN(reg1, reg2) => self.do_subn(reg1, reg2),
                     Op::SYS(addr) => self.do_sys(addr),
@@ -190,28 +420,26 @@ impl Emulator {
         }
     }
 
+    /// Whether the buzzer should be sounding, i.e. the sound timer is running.
+    pub fn sound_active(&self) -> bool {
+        self.st > 0
+    }
+
+    /// Queues a key press for the next instruction boundary.
     pub fn key_press(&mut self, key: Input) {
         debug!("KEY PRESS: {:?}", key);
-        self.keyboard[key as usize] = true;
+        self.keypad.push(self.cycles, key, true);
     }
 
+    /// Queues a key release. A key pressed during the current frame stays down until the end
+    /// of it, see [`Keypad`].
     pub fn key_release(&mut self, key: Input) {
         debug!("KEY RELEASE: {:?}", key);
-        self.keyboard[key as usize] = false;
-    }
-
-    fn pressed_key(&self) -> Option<u8> {
-        self.keyboard.iter().enumerate().find_map(|(i, pressed)| {
-            if *pressed == true {
-                Some(i as u8)
-            } else {
-                None
-            }
-        })
+        self.keypad.push(self.cycles, key, false);
     }
 
-    fn is_pressed(&self, key: u8) -> bool {
-        self.keyboard[key as usize]
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
     }
 
     fn do_add(&mut self, reg: Register, val: Value) -> StepResult {
@@ -223,7 +451,7 @@ impl Emulator {
     }
 
     fn do_addi(&mut self, reg: Register) -> StepResult {
-        self.i += self.vx[reg] as u16;
+        self.i += self.vx[reg] as usize;
 
         Ok(Some(Step::Nop))
     }
@@ -241,20 +469,53 @@ impl Emulator {
         Ok(Some(Step::Nop))
     }
 
+    fn do_alpha(&mut self, val: Value) -> StepResult {
+        if let Some(screen) = self.color_screen.as_mut() {
+            screen.set_alpha(val.into());
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
     fn do_and(&mut self, reg1: Register, reg2: Register) -> StepResult {
         self.vx[reg1] &= self.vx[reg2];
         Ok(Some(Step::Nop))
     }
 
+    fn do_bmode(&mut self, val: Value) -> StepResult {
+        if let Some(screen) = self.color_screen.as_mut() {
+            screen.set_blend_mode(display::BlendMode::from(val.0));
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
     fn do_call(&mut self, addr: Address) -> StepResult {
         self.push_to_stack(self.pc)?;
         self.pc = addr.into();
         Ok(Some(Step::Nop))
     }
 
+    fn do_ccol(&mut self, val: Value) -> StepResult {
+        if let Some(screen) = self.color_screen.as_mut() {
+            screen.set_collision_index(val.into());
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
     fn do_cls(&mut self) -> StepResult {
+        // MEGA-CHIP programs draw into a back buffer and present it by clearing the screen.
+        if let Some(screen) = self.color_screen.as_mut() {
+            let frame = screen.frame();
+            screen.clear();
+            self.mark_screen_dirty();
+            return Ok(Some(Step::DrawColor(frame)));
+        }
+
         self.screen.clear();
-        Ok(Some(Step::Draw(self.screen.pixels())))
+        self.mark_screen_dirty();
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
     }
 
     fn do_cpdt(&mut self, reg: Register) -> StepResult {
@@ -262,16 +523,69 @@ impl Emulator {
         Ok(Some(Step::Nop))
     }
 
+    // The sample header is a 16-bit rate and a 24-bit length, followed by a pad byte
+    // and then the unsigned 8-bit samples themselves.
+    fn do_digisnd(&mut self, val: Value) -> StepResult {
+        let header = self.memory_at(self.i, 6)?;
+        let rate = ((header[0] as u16) << 8) | header[1] as u16;
+        let len = ((header[2] as usize) << 16) | ((header[3] as usize) << 8) | header[4] as usize;
+
+        let start = self.i + 6;
+        let end = (start + len).min(self.memory.len());
+
+        Ok(Some(Step::PlaySample(Sample {
+            rate,
+            data: self.memory[start..end].to_vec(),
+            looping: val.0 == 0,
+        })))
+    }
+
     fn do_drw(&mut self, reg1: Register, reg2: Register, n: Value) -> StepResult {
         let x = self.vx[reg1];
         let y = self.vx[reg2];
-        let sprite_data = self.memory[self.i as usize..(self.i + n.0 as u16) as usize].to_vec();
 
-        if let Some(v) = self.screen.draw(display::Sprite::new(x, y, sprite_data)) {
-            self.vx[0xF] = v;
+        if let Some((width, height)) = self.color_screen.as_ref().map(|s| s.sprite_size()) {
+            let sprite_data = self.memory_at(self.i, width * height)?.to_vec();
+            if let Some(screen) = self.color_screen.as_mut() {
+                self.vx[0xF] = screen.draw(display::ColorSprite::new(x, y, width, sprite_data));
+            }
+            self.mark_screen_dirty();
+
+            return Ok(Some(Step::Nop));
         }
 
-        Ok(Some(Step::Draw(self.screen.pixels())))
+        let hires = self.screen.is_hires();
+        let sprite = match (n.0, hires, self.quirks.lores_dxy0) {
+            (0, true, _) | (0, false, LoresDxy0::Big) => {
+                display::Sprite::wide(x, y, self.memory_at(self.i, 32)?.to_vec())
+            }
+            (0, false, LoresDxy0::Tall) => {
+                display::Sprite::new(x, y, self.memory_at(self.i, 16)?.to_vec())
+            }
+            _ => display::Sprite::new(x, y, self.memory_at(self.i, n.0 as usize)?.to_vec()),
+        };
+
+        let drawn = if hires && self.quirks.hires_collision_rows {
+            self.screen.draw_counting_rows(sprite)
+        } else {
+            self.screen.draw(sprite)
+        };
+
+        if let Some(drawn) = drawn {
+            self.vx[0xF] = drawn.flag;
+            if let Some(rect) = drawn.dirty {
+                self.dirty = display::extend(self.dirty, rect);
+            }
+        }
+
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
+    }
+
+    fn do_high(&mut self) -> StepResult {
+        self.screen
+            .set_hires(true, self.quirks.clear_on_resolution_change);
+        self.mark_screen_dirty();
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
     }
 
     fn do_jp(&mut self, addr: Address) -> StepResult {
@@ -295,6 +609,20 @@ impl Emulator {
         Ok(Some(Step::Nop))
     }
 
+    fn do_ldhf(&mut self, reg: Register) -> StepResult {
+        let digit = (self.vx[reg] & 0x0F) as usize;
+        self.i = BIG_FONT_START + digit * 10;
+        Ok(Some(Step::Nop))
+    }
+
+    fn do_ldhi(&mut self, val: Value) -> StepResult {
+        let word = self.memory_at(self.pc, 2)?;
+        let lo = ((word[0] as usize) << 8) | word[1] as usize;
+        self.pc += 2;
+        self.i = ((val.0 as usize) << 16) | lo;
+        Ok(Some(Step::Nop))
+    }
+
     fn do_ldi(&mut self, addr: Address) -> StepResult {
         self.i = addr.into();
         Ok(Some(Step::Nop))
@@ -305,7 +633,7 @@ impl Emulator {
         let bcd = to_bcd(val);
 
         for j in 0usize..3usize {
-            self.memory[self.i as usize + j] = bcd[j]
+            self.memory[self.i + j] = bcd[j]
         }
 
         Ok(Some(Step::Nop))
@@ -314,29 +642,31 @@ impl Emulator {
     fn do_ldim(&mut self, reg: Register) -> StepResult {
         let Register(x) = reg;
         for r in 0..=x {
-            self.vx[r as usize] = self.memory[self.i as usize + r as usize];
+            self.vx[r as usize] = self.memory[self.i + r as usize];
         }
 
+        self.increment_index(x);
         Ok(Some(Step::Nop))
     }
 
     fn do_ldir(&mut self, reg: Register) -> StepResult {
         let Register(x) = reg;
         for r in 0..=x {
-            self.memory[self.i as usize + r as usize] = self.vx[r as usize];
+            self.memory[self.i + r as usize] = self.vx[r as usize];
         }
 
+        self.increment_index(x);
         Ok(Some(Step::Nop))
     }
 
     fn do_ldis(&mut self, reg: Register) -> StepResult {
         let digit = self.vx[reg];
-        self.i = (digit * 4) as u16;
+        self.i = (digit * 4) as usize;
         Ok(Some(Step::Nop))
     }
 
     fn do_ldkp(&mut self, reg: Register) -> StepResult {
-        if let Some(key) = self.pressed_key() {
+        if let Some(key) = self.keypad.wait_for_key() {
             self.vx[reg] = key;
             Ok(Some(Step::Nop))
         } else {
@@ -345,14 +675,70 @@ impl Emulator {
         }
     }
 
+    fn do_ldpal(&mut self, val: Value) -> StepResult {
+        if self.color_screen.is_some() {
+            let colors: Vec<display::Color> = self
+                .memory_at(self.i, val.0 as usize * 4)?
+                .chunks(4)
+                .map(|c| {
+                    ((c[0] as u32) << 24)
+                        | ((c[1] as u32) << 16)
+                        | ((c[2] as u32) << 8)
+                        | c[3] as u32
+                })
+                .collect();
+            if let Some(screen) = self.color_screen.as_mut() {
+                screen.load_palette(&colors);
+            }
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
     fn do_ldr(&mut self, reg1: Register, reg2: Register) -> StepResult {
         self.vx[reg1] = self.vx[reg2];
         Ok(Some(Step::Nop))
     }
 
+    fn do_ldrpl(&mut self, reg: Register) -> StepResult {
+        let Register(x) = reg;
+        for r in 0..=x as usize {
+            self.vx[r] = self.rpl[r];
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
     fn do_ldst(&mut self, reg: Register) -> StepResult {
+        let was_active = self.sound_active();
         self.st = self.vx[reg];
-        Ok(Some(Step::Nop))
+        Ok(Some(match (was_active, self.sound_active()) {
+            (false, true) => Step::SoundOn,
+            (true, false) => Step::SoundOff,
+            _ => Step::Nop,
+        }))
+    }
+
+    fn do_low(&mut self) -> StepResult {
+        self.screen
+            .set_hires(false, self.quirks.clear_on_resolution_change);
+        self.mark_screen_dirty();
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
+    }
+
+    fn do_megaoff(&mut self) -> StepResult {
+        self.color_screen = None;
+        self.screen.clear();
+        self.mark_screen_dirty();
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
+    }
+
+    fn do_megaon(&mut self) -> StepResult {
+        let screen = display::ColorScreen::default();
+        let frame = screen.frame();
+        self.color_screen = Some(screen);
+        self.mark_screen_dirty();
+        Ok(Some(Step::DrawColor(frame)))
     }
 
     fn do_or(&mut self, reg1: Register, reg2: Register) -> StepResult {
@@ -367,13 +753,75 @@ impl Emulator {
     }
 
     fn do_rnd(&mut self, reg: Register, val: Value) -> StepResult {
-        let mut rng = rand::thread_rng();
-        let r = rng.gen_range(std::u8::MIN, std::u8::MAX);
+        let r = self.rng.gen_range(std::u8::MIN, std::u8::MAX);
         self.vx[reg] = r & val.0;
 
         Ok(Some(Step::Nop))
     }
 
+    fn do_scd(&mut self, val: Value) -> StepResult {
+        if self.color_screen.is_some() {
+            return self.scroll_color(0, val.0 as isize);
+        }
+
+        let n = self.scroll_amount(val.0 as usize);
+        self.screen.framebuffer_mut().scroll_down(n);
+        self.mark_screen_dirty();
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
+    }
+
+    fn do_scl(&mut self) -> StepResult {
+        if self.color_screen.is_some() {
+            return self.scroll_color(-4, 0);
+        }
+
+        let n = self.scroll_amount(4);
+        self.screen.framebuffer_mut().scroll_left(n);
+        self.mark_screen_dirty();
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
+    }
+
+    fn do_scr(&mut self) -> StepResult {
+        if self.color_screen.is_some() {
+            return self.scroll_color(4, 0);
+        }
+
+        let n = self.scroll_amount(4);
+        self.screen.framebuffer_mut().scroll_right(n);
+        self.mark_screen_dirty();
+        Ok(Some(Step::Draw(self.screen.framebuffer().clone())))
+    }
+
+    // In MEGA-CHIP mode the scroll instructions move the colour screen, by the full distance.
+    fn scroll_color(&mut self, dx: isize, dy: isize) -> StepResult {
+        let frame = match self.color_screen.as_mut() {
+            Some(screen) => {
+                screen.scroll(dx, dy);
+                screen.frame()
+            }
+            None => return Ok(Some(Step::Nop)),
+        };
+        self.mark_screen_dirty();
+        Ok(Some(Step::DrawColor(frame)))
+    }
+
+    fn mark_screen_dirty(&mut self) {
+        let area = match self.color_screen.as_ref() {
+            Some(screen) => screen.area(),
+            None => self.screen.area(),
+        };
+        self.dirty = display::extend(self.dirty, area);
+    }
+
+    // SUPER-CHIP 1.x measures scroll amounts in high resolution pixels, even in low resolution.
+    fn scroll_amount(&self, n: usize) -> usize {
+        if !self.screen.is_hires() && self.quirks.half_scroll_lores {
+            n / 2
+        } else {
+            n
+        }
+    }
+
     fn do_se(&mut self, reg: Register, val: Value) -> StepResult {
         if self.vx[reg] == val.into() {
             self.pc += 2;
@@ -409,7 +857,7 @@ impl Emulator {
 
     fn do_sknp(&mut self, reg: Register) -> StepResult {
         let key = self.vx[reg];
-        if !self.is_pressed(key) {
+        if !self.keypad.poll(key) {
             self.pc += 2;
         }
 
@@ -418,7 +866,7 @@ impl Emulator {
 
     fn do_skp(&mut self, reg: Register) -> StepResult {
         let key = self.vx[reg];
-        if self.is_pressed(key) {
+        if self.keypad.poll(key) {
             self.pc += 2;
         }
 
@@ -441,6 +889,41 @@ impl Emulator {
         Ok(Some(Step::Nop))
     }
 
+    fn do_sprh(&mut self, val: Value) -> StepResult {
+        if let Some(screen) = self.color_screen.as_mut() {
+            screen.set_sprite_height(val.into());
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
+    fn do_sprw(&mut self, val: Value) -> StepResult {
+        if let Some(screen) = self.color_screen.as_mut() {
+            screen.set_sprite_width(val.into());
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
+    fn do_stopsnd(&mut self) -> StepResult {
+        Ok(Some(Step::StopSample))
+    }
+
+    fn do_strpl(&mut self, reg: Register) -> StepResult {
+        let Register(x) = reg;
+        let count = x as usize + 1;
+        if self.rpl[..count] == self.vx[..count] {
+            return Ok(Some(Step::Nop));
+        }
+
+        self.rpl[..count].copy_from_slice(&self.vx[..count]);
+        if let Some(store) = self.flag_store.as_ref() {
+            store.save_or_warn(&self.rpl);
+        }
+
+        Ok(Some(Step::Nop))
+    }
+
     fn do_sub(&mut self, reg1: Register, reg2: Register) -> StepResult {
         let (v, overflow) = self.vx[reg1].overflowing_sub(self.vx[reg2]);
 
@@ -476,6 +959,21 @@ impl Emulator {
         Ok(Some(Step::Nop))
     }
 
+    // `len` bytes of memory from `addr`, which a ROM can point anywhere.
+    fn memory_at(&self, addr: usize, len: usize) -> Result<&[u8]> {
+        self.memory
+            .get(addr..addr + len)
+            .ok_or(Error::AddressOutOfRange(addr))
+    }
+
+    fn increment_index(&mut self, x: u8) {
+        match self.quirks.load_store {
+            IndexIncrement::Unchanged => {}
+            IndexIncrement::X => self.i += x as usize,
+            IndexIncrement::XPlusOne => self.i += x as usize + 1,
+        }
+    }
+
     fn push_to_stack(&mut self, val: usize) -> Result<()> {
         if self.sp as usize >= STACK_SIZE {
             return Err(Error::StackOverflow);
@@ -495,3 +993,281 @@ impl Emulator {
         Ok(self.stack[self.sp as usize])
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn megachip(rom: &[u8]) -> Emulator {
+        Emulator::with_platform(rom, Platform::MegaChip).unwrap()
+    }
+
+    fn out_of_range(result: StepResult) -> Option<usize> {
+        match result {
+            Err(Error::AddressOutOfRange(addr)) => Some(addr),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_flags_persist() {
+        let dir = std::env::temp_dir().join(format!("chip8-flags-{}", std::process::id()));
+        let store = || storage::FlagStore::new(dir.join("test.flags"));
+
+        // V0 = 42, V1 = 7, save V0 - V1
+        let rom = [0x60, 0x2A, 0x61, 0x07, 0xF1, 0x75];
+        let mut emu = Emulator::with_platform(&rom[..], Platform::SuperChip11).unwrap();
+        emu.set_flag_store(store());
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+
+        // restore V0 - V1 in a new session
+        let rom = [0xF1, 0x85];
+        let mut emu = Emulator::with_platform(&rom[..], Platform::SuperChip11).unwrap();
+        emu.set_flag_store(store());
+        emu.step().unwrap();
+        assert_eq!(emu.vx[..2], [42, 7]);
+
+        // without a store nothing is loaded
+        let mut emu = Emulator::with_platform(&rom[..], Platform::SuperChip11).unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.vx[..2], [0, 0]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_digisnd_out_of_range() {
+        // LDHI 0xFFFFFE, DIGISND 0: the header runs past the end of memory
+        let mut emu = megachip(&[0x01, 0xFF, 0xFF, 0xFE, 0x06, 0x00]);
+        emu.step().unwrap();
+        assert_eq!(out_of_range(emu.step()), Some(0xFF_FFFE));
+
+        // a header that fits, with the samples cut off at the end of memory
+        let mut emu = megachip(&[0x01, 0xFF, 0xFF, 0xF8, 0x06, 0x01]);
+        emu.memory[0xFF_FFF8..0xFF_FFFE].copy_from_slice(&[0x1F, 0x40, 0, 0, 9, 0]);
+        emu.memory[0xFF_FFFE..].copy_from_slice(&[0x80, 0xFF]);
+        emu.step().unwrap();
+        match emu.step().unwrap() {
+            Some(Step::PlaySample(sample)) => {
+                assert_eq!(sample.rate, 8000);
+                assert_eq!(sample.data, vec![0x80, 0xFF]);
+                assert!(!sample.looping);
+            }
+            _ => panic!("no sample"),
+        }
+    }
+
+    #[test]
+    fn test_ldpal_out_of_range() {
+        // MEGAON, LDHI 0xFFFFFC, LDPAL 2
+        let rom = [0x00, 0x11, 0x01, 0xFF, 0xFF, 0xFC, 0x02, 0x02];
+        let mut emu = megachip(&rom);
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(out_of_range(emu.step()), Some(0xFF_FFFC));
+
+        // one colour still fits
+        let mut emu = megachip(&[0x00, 0x11, 0x01, 0xFF, 0xFF, 0xFC, 0x02, 0x01]);
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ldhi_out_of_range() {
+        // LDHI as the last instruction in memory, with no room for its address
+        let mut emu = megachip(&[0x00, 0xE0]);
+        let end = emu.memory.len();
+        emu.memory[end - 2..].copy_from_slice(&[0x01, 0x12]);
+        emu.rom_end = end;
+        emu.pc = end - 2;
+        assert_eq!(out_of_range(emu.step()), Some(end));
+    }
+
+    #[test]
+    fn test_dirty() {
+        // I = digit 0, draw it at (2, 3) and at (20, 10), then CLS
+        let rom = [
+            0xF0, 0x29, 0x61, 0x02, 0x62, 0x03, 0xD1, 0x25, 0x63, 0x14, 0x64, 0x0A, 0xD3, 0x45,
+            0x00, 0xE0,
+        ];
+        let mut emu = Emulator::new(&rom[..]).unwrap();
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.take_dirty(), None);
+
+        // both draws before the region is taken
+        for _ in 0..4 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.take_dirty(), Some(display::Rect::new(2, 3, 22, 12)));
+        assert_eq!(emu.take_dirty(), None);
+
+        emu.step().unwrap();
+        assert_eq!(emu.take_dirty(), Some(display::Rect::new(0, 0, 64, 32)));
+    }
+
+    #[test]
+    fn test_reset() {
+        // V0 = 0xAB, I = 0x200, store V0 over the first byte of the ROM, call 0x20A, which
+        // draws digit 0 at (0, 0), sets both timers to V0 and loops
+        let rom = [
+            0x60, 0xAB, 0xA2, 0x00, 0xF0, 0x55, 0x22, 0x0A, 0x00, 0x00, 0xF1, 0x29, 0xD1, 0x15,
+            0xF0, 0x15, 0xF0, 0x18, 0x12, 0x12,
+        ];
+        let mut emu = Emulator::new(&rom[..]).unwrap();
+        emu.key_press(Input::Key5);
+        for _ in 0..8 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.memory[MEM_START], 0xAB);
+        assert_eq!(emu.sp, 1);
+        assert!(emu.keypad().is_pressed(5));
+        assert!(emu.screen.framebuffer().bits().iter().any(|&b| b != 0));
+        emu.take_dirty();
+
+        emu.reset();
+        assert_eq!(emu.vx, [0; REG_COUNT]);
+        assert_eq!((emu.dt, emu.st, emu.sp, emu.i), (0, 0, 0, 0));
+        assert_eq!(emu.pc, MEM_START);
+        assert_eq!(emu.stack, [0; STACK_SIZE]);
+        assert_eq!(emu.memory[MEM_START..emu.rom_end], rom[..]);
+        assert_eq!(emu.keypad().pressed_keys().count(), 0);
+        assert!(emu.screen.framebuffer().bits().iter().all(|&b| b == 0));
+        assert_eq!(emu.take_dirty(), Some(display::Rect::new(0, 0, 64, 32)));
+
+        // and it runs the same way again
+        emu.step().unwrap();
+        assert_eq!(emu.vx[0], 0xAB);
+    }
+
+    #[test]
+    fn test_frames() {
+        // V0 = 3, DT = V0, ST = V0, then loop forever
+        let rom = [0x60, 0x03, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06];
+        let mut emu = Emulator::new(&rom[..]).unwrap();
+        emu.key_press(Input::Key5);
+        emu.key_release(Input::Key5);
+        for _ in 0..10 {
+            emu.step().unwrap();
+        }
+        // instructions alone leave the timers running, and a tapped key down for the frame
+        assert_eq!((emu.dt, emu.st), (3, 3));
+        assert!(emu.keypad().is_pressed(5));
+
+        emu.start_frame();
+        emu.tick_timers();
+        assert_eq!((emu.dt, emu.st), (2, 2));
+        assert!(!emu.keypad().is_pressed(5));
+    }
+
+    fn sound_event(step: Option<Step>) -> Option<bool> {
+        match step {
+            Some(Step::SoundOn) => Some(true),
+            Some(Step::SoundOff) => Some(false),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_sound_events() {
+        // V0 = 2, ST = V0 twice, then V1 = 0, ST = V1
+        let rom = [0x60, 0x02, 0xF0, 0x18, 0xF0, 0x18, 0x61, 0x00, 0xF1, 0x18];
+        let mut emu = Emulator::new(&rom[..]).unwrap();
+        emu.step().unwrap();
+        assert_eq!(sound_event(emu.step().unwrap()), Some(true));
+        assert_eq!(sound_event(emu.step().unwrap()), None);
+
+        // the timer runs out on the second tick
+        assert_eq!(sound_event(emu.tick_timers()), None);
+        assert_eq!(sound_event(emu.tick_timers()), Some(false));
+        assert_eq!(sound_event(emu.tick_timers()), None);
+
+        // and setting it to 0 stops it too
+        emu.pc = MEM_START + 2;
+        assert_eq!(sound_event(emu.step().unwrap()), Some(true));
+        emu.pc = MEM_START + 6;
+        emu.step().unwrap();
+        assert_eq!(sound_event(emu.step().unwrap()), Some(false));
+    }
+
+    #[test]
+    fn test_scroll_megachip() {
+        // MEGAON, SCR, SCD 3
+        let mut emu = megachip(&[0x00, 0x11, 0x00, 0xFB, 0x00, 0xC3]);
+        emu.step().unwrap();
+        let screen = emu.color_screen.as_mut().unwrap();
+        screen.load_palette(&[0xFFFF_0000]);
+        screen.draw(display::ColorSprite::new(10, 10, 1, vec![1]));
+
+        let lit = |step: StepResult| match step {
+            Ok(Some(Step::DrawColor(frame))) => frame
+                .colors
+                .iter()
+                .position(|&c| c != 0)
+                .map(|i| (i % frame.width as usize, i / frame.width as usize)),
+            _ => panic!("expected a colour frame"),
+        };
+        assert_eq!(lit(emu.step()), Some((14, 10)));
+        assert_eq!(lit(emu.step()), Some((14, 13)));
+        assert!(emu.screen.framebuffer().bits().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_drw_out_of_range() {
+        // I = 0xFFE, DRW V0, V0, 5: the sprite runs past the end of memory
+        let mut emu = Emulator::new(&[0xAF, 0xFE, 0xD0, 0x05][..]).unwrap();
+        emu.step().unwrap();
+        assert_eq!(out_of_range(emu.step()), Some(0xFFE));
+
+        // MEGAON, LDHI 0xFFFFFF, SPRW 2, SPRH 1, DRW V0, V0: I is at the last byte
+        let rom = [
+            0x00, 0x11, 0x01, 0xFF, 0xFF, 0xFF, 0x03, 0x02, 0x04, 0x01, 0xD0, 0x00,
+        ];
+        let mut emu = megachip(&rom);
+        for _ in 0..4 {
+            emu.step().unwrap();
+        }
+        assert_eq!(out_of_range(emu.step()), Some(0xFF_FFFF));
+
+        // MEGAON, LDHI 0xFFFFFF, V0 = 5, ADD I, V0, DRW V0, V0: I is past the end
+        let rom = [
+            0x00, 0x11, 0x01, 0xFF, 0xFF, 0xFF, 0x60, 0x05, 0xF0, 0x1E, 0xD0, 0x00,
+        ];
+        let mut emu = megachip(&rom);
+        for _ in 0..4 {
+            emu.step().unwrap();
+        }
+        assert_eq!(out_of_range(emu.step()), Some(0x100_0004));
+    }
+
+    #[test]
+    fn test_chip8_stays_lores() {
+        // HIGH, then SCR: machine code calls on a plain CHIP-8, so nothing happens
+        let rom = [0x00, 0xFF, 0x00, 0xFB];
+        let mut emu = Emulator::new(&rom[..]).unwrap();
+        assert!(matches!(emu.step(), Ok(Some(Step::Nop))));
+        assert!(!emu.screen.is_hires());
+        assert!(matches!(emu.step(), Ok(Some(Step::Nop))));
+
+        let mut emu = Emulator::with_platform(&rom[..], Platform::SuperChip11).unwrap();
+        emu.step().unwrap();
+        assert!(emu.screen.is_hires());
+
+        // and there are no user flags to save
+        let mut emu = Emulator::new(&[0xF1, 0x75][..]).unwrap();
+        assert!(matches!(emu.step(), Err(Error::UnknownInstruction(0xF175))));
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("0"), Some(Input::Key0));
+        assert_eq!(parse_key("b"), Some(Input::KeyB));
+        assert_eq!(parse_key("F"), Some(Input::KeyF));
+        assert_eq!(parse_key("10"), None);
+        assert_eq!(parse_key("g"), None);
+    }
+}
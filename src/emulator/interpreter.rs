@@ -1,4 +1,5 @@
 use super::common::{Error, Result};
+use super::storage::RPL_COUNT;
 use std::fmt;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -47,26 +48,42 @@ pub enum Op {
     ADD(Register, Value),
     ADDI(Register),
     ADDR(Register, Register),
+    ALPHA(Value),
     AND(Register, Register),
+    BMODE(Value),
     CALL(Address),
+    CCOL(Value),
     CLS,
     CPDT(Register),
+    DIGISND(Value),
     DRW(Register, Register, Value),
+    EXIT,
+    HIGH,
     JP(Address),
     JPREL(Address),
     LD(Register, Value),
     LDDT(Register),
+    LDHF(Register),
+    LDHI(Value),
     LDI(Address),
     LDIB(Register),
     LDIM(Register),
     LDIR(Register),
     LDIS(Register),
     LDKP(Register),
+    LDPAL(Value),
     LDR(Register, Register),
+    LDRPL(Register),
     LDST(Register),
+    LOW,
+    MEGAOFF,
+    MEGAON,
     OR(Register, Register),
     RET,
     RND(Register, Value),
+    SCD(Value),
+    SCL,
+    SCR,
     SE(Register, Value),
     SER(Register, Register),
     SHL(Register),
@@ -75,6 +92,10 @@ pub enum Op {
     SKP(Register),
     SNE(Register, Value),
     SNER(Register, Register),
+    SPRH(Value),
+    SPRW(Value),
+    STOPSND,
+    STRPL(Register),
     SUB(Register, Register),
     SUBN(Register, Register),
     SYS(Address),
@@ -87,6 +108,12 @@ impl fmt::Display for Op {
             Op::SYS(Address(addr)) => f.write_fmt(format_args!("SYS #{}", addr)),
             Op::CLS => f.write_str("CLS"),
             Op::RET => f.write_str("RET"),
+            Op::SCD(Value(val)) => f.write_fmt(format_args!("SCD {}", val)),
+            Op::SCR => f.write_str("SCR"),
+            Op::SCL => f.write_str("SCL"),
+            Op::EXIT => f.write_str("EXIT"),
+            Op::LOW => f.write_str("LOW"),
+            Op::HIGH => f.write_str("HIGH"),
             Op::JP(Address(addr)) => f.write_fmt(format_args!("JP #{}", addr)),
             Op::CALL(Address(addr)) => f.write_fmt(format_args!("CALL #{}", addr)),
             Op::SE(Register(reg), Value(val)) => f.write_fmt(format_args!("SE $V{} {}", reg, val)),
@@ -142,9 +169,23 @@ impl fmt::Display for Op {
             Op::LDST(Register(reg)) => f.write_fmt(format_args!("LDST $V{}", reg)),
             Op::ADDI(Register(reg)) => f.write_fmt(format_args!("ADDI $V{}", reg)),
             Op::LDIS(Register(reg)) => f.write_fmt(format_args!("LDIS $V{}", reg)),
+            Op::LDHF(Register(reg)) => f.write_fmt(format_args!("LDHF $V{}", reg)),
             Op::LDIB(Register(reg)) => f.write_fmt(format_args!("LDIB $V{}", reg)),
             Op::LDIR(Register(reg)) => f.write_fmt(format_args!("LDIR $V{}", reg)),
             Op::LDIM(Register(reg)) => f.write_fmt(format_args!("LDIM $V{}", reg)),
+            Op::STRPL(Register(reg)) => f.write_fmt(format_args!("STRPL $V{}", reg)),
+            Op::LDRPL(Register(reg)) => f.write_fmt(format_args!("LDRPL $V{}", reg)),
+            Op::MEGAOFF => f.write_str("MEGAOFF"),
+            Op::MEGAON => f.write_str("MEGAON"),
+            Op::LDHI(Value(val)) => f.write_fmt(format_args!("LDHI {}", val)),
+            Op::LDPAL(Value(val)) => f.write_fmt(format_args!("LDPAL {}", val)),
+            Op::SPRW(Value(val)) => f.write_fmt(format_args!("SPRW {}", val)),
+            Op::SPRH(Value(val)) => f.write_fmt(format_args!("SPRH {}", val)),
+            Op::ALPHA(Value(val)) => f.write_fmt(format_args!("ALPHA {}", val)),
+            Op::DIGISND(Value(val)) => f.write_fmt(format_args!("DIGISND {}", val)),
+            Op::STOPSND => f.write_str("STOPSND"),
+            Op::BMODE(Value(val)) => f.write_fmt(format_args!("BMODE {}", val)),
+            Op::CCOL(Value(val)) => f.write_fmt(format_args!("CCOL {}", val)),
         }
     }
 }
@@ -210,6 +251,48 @@ impl Instruction {
         }
     }
 
+    // SUPER-CHIP takes some of the 0NNN (SYS) space and adds to the FXNN instructions. On a
+    // plain CHIP-8 those stay machine code calls or unknown instructions.
+    pub fn interpret_schip(&self) -> Result<Op> {
+        match self.0 & 0xF0FF {
+            0x00FB => Ok(Op::SCR),
+            0x00FC => Ok(Op::SCL),
+            0x00FD => Ok(Op::EXIT),
+            0x00FE => Ok(Op::LOW),
+            0x00FF => Ok(Op::HIGH),
+            0xF030 => Ok(Op::LDHF(self.second_nibble())),
+            0xF075 | 0xF085 if self.second_nibble().0 as usize >= RPL_COUNT => {
+                Err(Error::UnknownInstruction(self.0))
+            }
+            0xF075 => Ok(Op::STRPL(self.second_nibble())),
+            0xF085 => Ok(Op::LDRPL(self.second_nibble())),
+            _ if self.0 & 0xFFF0 == 0x00C0 => Ok(Op::SCD(Value((self.0 & 0x000F) as u8))),
+            _ => self.interpret(),
+        }
+    }
+
+    // MEGA-CHIP reuses the 0NNN (SYS) space for its extensions, so these are
+    // only decoded when the ROM targets that platform.
+    pub fn interpret_megachip(&self) -> Result<Op> {
+        match self.0 & 0xFF00 {
+            0x0000 => match self.0 {
+                0x0010 => Ok(Op::MEGAOFF),
+                0x0011 => Ok(Op::MEGAON),
+                _ => self.interpret_schip(),
+            },
+            0x0100 => Ok(Op::LDHI(self.last_byte())),
+            0x0200 => Ok(Op::LDPAL(self.last_byte())),
+            0x0300 => Ok(Op::SPRW(self.last_byte())),
+            0x0400 => Ok(Op::SPRH(self.last_byte())),
+            0x0500 => Ok(Op::ALPHA(self.last_byte())),
+            0x0600 => Ok(Op::DIGISND(Value((self.0 & 0x000F) as u8))),
+            0x0700 => Ok(Op::STOPSND),
+            0x0800 => Ok(Op::BMODE(Value((self.0 & 0x000F) as u8))),
+            0x0900 => Ok(Op::CCOL(self.last_byte())),
+            _ => self.interpret_schip(),
+        }
+    }
+
     // Consider an instruction such as ABCD
     // second_nibble = B
     // third_nibble = C
@@ -303,6 +386,73 @@ mod test {
     test_instruction_ok!(test_ldir, 0xF855, Op::LDIR(Register(0x8)));
     test_instruction_ok!(test_ldim, 0xF865, Op::LDIM(Register(0x8)));
 
+    macro_rules! test_schip_instruction_ok {
+        ($name:ident, $input:literal, $want:expr) => {
+            #[test]
+            fn $name() {
+                let got_op = Instruction($input).interpret_schip();
+                assert!(got_op.is_ok());
+                assert_eq!($want, got_op.unwrap());
+            }
+        };
+    }
+
+    test_schip_instruction_ok!(test_scd, 0x00C4, Op::SCD(Value(0x4)));
+    test_schip_instruction_ok!(test_scr, 0x00FB, Op::SCR);
+    test_schip_instruction_ok!(test_scl, 0x00FC, Op::SCL);
+    test_schip_instruction_ok!(test_exit, 0x00FD, Op::EXIT);
+    test_schip_instruction_ok!(test_low, 0x00FE, Op::LOW);
+    test_schip_instruction_ok!(test_high, 0x00FF, Op::HIGH);
+    test_schip_instruction_ok!(test_ldhf, 0xF830, Op::LDHF(Register(0x8)));
+    test_schip_instruction_ok!(test_strpl, 0xF775, Op::STRPL(Register(0x7)));
+    test_schip_instruction_ok!(test_ldrpl, 0xF785, Op::LDRPL(Register(0x7)));
+    test_schip_instruction_ok!(test_schip_cls, 0x00E0, Op::CLS);
+    test_schip_instruction_ok!(test_schip_sys, 0x0123, Op::SYS(Address(0x123)));
+
+    #[test]
+    fn test_schip_only() {
+        // without SUPER-CHIP these are machine code calls or unknown
+        for &ins in &[0x00FF, 0x00FE, 0x00FB, 0x00C4] {
+            assert_eq!(Instruction(ins).interpret().unwrap(), Op::SYS(Address(ins)));
+        }
+        for &ins in &[0xF330, 0xF775, 0xF785] {
+            assert!(Instruction(ins).interpret().is_err());
+        }
+
+        // SUPER-CHIP only has 8 user flags
+        assert!(Instruction(0xF875).interpret_schip().is_err());
+        assert!(Instruction(0xF885).interpret_schip().is_err());
+    }
+
+    macro_rules! test_megachip_instruction_ok {
+        ($name:ident, $input:literal, $want:expr) => {
+            #[test]
+            fn $name() {
+                let got_op = Instruction($input).interpret_megachip();
+                assert!(got_op.is_ok());
+                assert_eq!($want, got_op.unwrap());
+            }
+        };
+    }
+
+    test_megachip_instruction_ok!(test_megaoff, 0x0010, Op::MEGAOFF);
+    test_megachip_instruction_ok!(test_megaon, 0x0011, Op::MEGAON);
+    test_megachip_instruction_ok!(test_ldhi, 0x0112, Op::LDHI(Value(0x12)));
+    test_megachip_instruction_ok!(test_ldpal, 0x0210, Op::LDPAL(Value(0x10)));
+    test_megachip_instruction_ok!(test_sprw, 0x0320, Op::SPRW(Value(0x20)));
+    test_megachip_instruction_ok!(test_sprh, 0x0418, Op::SPRH(Value(0x18)));
+    test_megachip_instruction_ok!(test_alpha, 0x05FF, Op::ALPHA(Value(0xFF)));
+    test_megachip_instruction_ok!(test_digisnd, 0x0601, Op::DIGISND(Value(0x1)));
+    test_megachip_instruction_ok!(test_stopsnd, 0x0700, Op::STOPSND);
+    test_megachip_instruction_ok!(test_bmode, 0x0803, Op::BMODE(Value(0x3)));
+    test_megachip_instruction_ok!(test_ccol, 0x0904, Op::CCOL(Value(0x4)));
+    test_megachip_instruction_ok!(test_megachip_cls, 0x00E0, Op::CLS);
+    test_megachip_instruction_ok!(
+        test_megachip_drw,
+        0xD120,
+        Op::DRW(Register(0x1), Register(0x2), Value(0x0))
+    );
+
     #[test]
     fn test_to_bcd() {
         assert_eq!([1, 2, 3], to_bcd(123));
@@ -0,0 +1,198 @@
+use super::implementation::Input;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct KeyEvent {
+    at: u64, // instruction count
+    key: usize,
+    pressed: bool,
+}
+
+/// The state of the 16 keys. Presses and releases are queued with the instruction count they
+/// happened at and take effect at the next instruction boundary, in the order they came in. A
+/// key that goes down stays down until the end of the frame even if it is released sooner, so
+/// programs that look at the keys once a frame see short taps too.
+#[derive(Debug, Default)]
+pub struct Keypad {
+    held: [bool; 16],
+    pressed_this_frame: [bool; 16],
+    polled: [bool; 16],      // looked at by the program during this frame
+    last_polled: [bool; 16], // and during the last one
+    queue: VecDeque<KeyEvent>,
+    wait: Option<Wait>,
+}
+
+// An FX0A in progress: it finishes when a key that is down gets released.
+#[derive(Debug, Default)]
+struct Wait {
+    released: Option<u8>,
+}
+
+impl Keypad {
+    pub fn push(&mut self, at: u64, key: Input, pressed: bool) {
+        self.queue.push_back(KeyEvent {
+            at,
+            key: key as usize,
+            pressed,
+        });
+    }
+
+    /// Applies the events queued up to instruction `now`. Releases of keys that went down this
+    /// frame are held back until `end_frame`, together with anything that follows them for the
+    /// same key.
+    pub fn apply(&mut self, now: u64) {
+        let mut deferred = [false; 16];
+        let mut queue = VecDeque::with_capacity(self.queue.len());
+        for event in self.queue.drain(..) {
+            let key = event.key;
+            if event.at > now
+                || deferred[key]
+                || (!event.pressed && self.pressed_this_frame[key] && self.held[key])
+            {
+                deferred[key] = true;
+                queue.push_back(event);
+                continue;
+            }
+
+            if event.pressed {
+                self.pressed_this_frame[key] |= !self.held[key];
+            } else if self.held[key] {
+                if let Some(wait) = self.wait.as_mut() {
+                    wait.released.get_or_insert(key as u8);
+                }
+            }
+            self.held[key] = event.pressed;
+            // one change per key and boundary, so a release and a new press are both seen
+            deferred[key] = true;
+        }
+        self.queue = queue;
+    }
+
+    /// Marks a frame boundary: keys tapped during the frame can be released now.
+    pub fn end_frame(&mut self, now: u64) {
+        self.pressed_this_frame = [false; 16];
+        self.last_polled = self.polled;
+        self.polled = [false; 16];
+        self.apply(now);
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.held[(key & 0xF) as usize]
+    }
+
+    /// Reads a key the way `EX9E` and `EXA1` do, noting that the program looked at it.
+    pub fn poll(&mut self, key: u8) -> bool {
+        self.polled[(key & 0xF) as usize] = true;
+        self.is_pressed(key)
+    }
+
+    /// Whether the program looked at the key during the last frame, by testing it or waiting
+    /// for any key.
+    pub fn polled(&self, key: u8) -> bool {
+        self.last_polled[(key & 0xF) as usize]
+    }
+
+    /// All keys that are down, lowest first.
+    pub fn pressed_keys(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..16u8).filter(move |key| self.held[*key as usize])
+    }
+
+    /// Waits for a key the way `FX0A` does on the COSMAC VIP: the key counts when it is
+    /// released, so holding a key does not repeat it. With several keys down, the first one to
+    /// go up is returned. Returns `None` while still waiting.
+    pub fn wait_for_key(&mut self) -> Option<u8> {
+        self.polled = [true; 16];
+        let key = self.wait.get_or_insert_with(Wait::default).released?;
+        self.wait = None;
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_several_keys() {
+        let mut keypad = Keypad::default();
+        keypad.push(0, Input::Key7, true);
+        keypad.push(0, Input::Key2, true);
+        keypad.apply(0);
+        assert!(keypad.is_pressed(7) && keypad.is_pressed(2));
+        assert!(keypad.is_pressed(0x17));
+        assert_eq!(keypad.pressed_keys().collect::<Vec<_>>(), vec![2, 7]);
+
+        // releasing one key leaves the other down
+        keypad.end_frame(1);
+        keypad.push(1, Input::Key7, false);
+        keypad.apply(1);
+        assert_eq!(keypad.pressed_keys().collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut keypad = Keypad::default();
+        keypad.push(5, Input::KeyA, true);
+        keypad.apply(4);
+        assert!(!keypad.is_pressed(0xA));
+        keypad.apply(5);
+        assert!(keypad.is_pressed(0xA));
+    }
+
+    #[test]
+    fn test_tap() {
+        let mut keypad = Keypad::default();
+        keypad.push(0, Input::Key5, true);
+        keypad.push(0, Input::Key5, false);
+        keypad.push(0, Input::Key5, true);
+        keypad.apply(0);
+        assert!(keypad.is_pressed(5));
+        keypad.apply(1);
+        assert!(keypad.is_pressed(5));
+
+        // the release takes effect at the end of the frame, the press after it at the next
+        // instruction
+        keypad.end_frame(2);
+        assert!(!keypad.is_pressed(5));
+        keypad.apply(3);
+        assert!(keypad.is_pressed(5));
+    }
+
+    #[test]
+    fn test_polled() {
+        let mut keypad = Keypad::default();
+        keypad.poll(4);
+        keypad.poll(0xC);
+        assert!(!keypad.polled(4));
+        keypad.end_frame(0);
+        assert!(keypad.polled(4) && keypad.polled(0xC) && !keypad.polled(5));
+
+        keypad.wait_for_key();
+        keypad.end_frame(1);
+        assert!((0..16).all(|key| keypad.polled(key)));
+        keypad.end_frame(2);
+        assert!(!keypad.polled(4));
+    }
+
+    #[test]
+    fn test_wait_for_key() {
+        let mut keypad = Keypad::default();
+        keypad.push(0, Input::Key3, true);
+        keypad.apply(0);
+        assert_eq!(keypad.wait_for_key(), None);
+
+        keypad.push(1, Input::Key9, true);
+        keypad.apply(1);
+        assert_eq!(keypad.wait_for_key(), None);
+
+        // the key released first wins, not the lowest
+        keypad.end_frame(2);
+        keypad.push(2, Input::Key9, false);
+        keypad.push(2, Input::Key3, false);
+        keypad.apply(2);
+        assert_eq!(keypad.wait_for_key(), Some(9));
+
+        // a key released before the wait started does not count
+        assert_eq!(keypad.wait_for_key(), None);
+    }
+}
@@ -1,12 +1,25 @@
 mod common;
 pub mod debugger;
-mod display;
+pub mod display;
 mod implementation;
 mod interpreter;
+mod keypad;
+pub mod movie;
+mod platform;
+pub mod storage;
 pub mod ui;
 
 pub type Error = common::Error;
 pub type Result<T> = common::Result<T>;
+pub type Edge = display::Edge;
 pub type Emulator = implementation::Emulator;
+pub type Framebuffer = display::Framebuffer;
 pub type Input = implementation::Input;
+pub type Keypad = keypad::Keypad;
+pub type IndexIncrement = platform::IndexIncrement;
+pub type LoresDxy0 = platform::LoresDxy0;
+pub type Platform = platform::Platform;
+pub type Quirks = platform::Quirks;
+pub type Rect = display::Rect;
+pub type Sample = implementation::Sample;
 pub type Step = implementation::Step;
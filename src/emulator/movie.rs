@@ -0,0 +1,277 @@
+use super::common::{Error, Result};
+use super::implementation::{self, Emulator, Input};
+use super::platform::{Platform, Quirks};
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &str = "chip8-movie 1";
+
+/// A key going down or up at the start of a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    pub key: Input,
+    pub pressed: bool,
+}
+
+/// Everything needed to play a session back exactly: the ROM it was made with, the platform,
+/// quirks, random seed and speed, and every key press and release with the frame it happened
+/// before.
+///
+/// Movies are stored as text, one line per event, so they can be read and edited by hand:
+///
+/// ```text
+/// chip8-movie 1
+/// rom 0123456789abcdef0123456789abcdef01234567
+/// platform chip8
+/// quirks lores-dxy0=nothing load-store=unchanged half-scroll-lores=false ...
+/// seed 42
+/// speed 1
+/// frames 300
+/// 12 press 5
+/// 20 release 5
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: String,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub speed: usize, // instructions per frame
+    pub frames: u64,  // length of the session
+    pub events: Vec<KeyEvent>,
+}
+
+impl Movie {
+    /// Starts an empty movie for the emulator running `speed` instructions per frame.
+    pub fn new(emu: &Emulator, seed: u64, speed: usize) -> Self {
+        Movie {
+            rom_hash: emu.rom_hash().to_string(),
+            platform: emu.platform(),
+            quirks: emu.quirks(),
+            seed,
+            speed,
+            frames: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, key: Input, pressed: bool) {
+        self.events.push(KeyEvent {
+            frame,
+            key,
+            pressed,
+        });
+    }
+
+    /// The events from index `next` up to the ones that happen before `frame`, moving `next` past
+    /// them. Events are kept in frame order, so a replay looks at each of them only once.
+    pub fn take_events(&self, next: &mut usize, frame: u64) -> &[KeyEvent] {
+        let start = (*next).min(self.events.len());
+        let due = self.events[start..]
+            .iter()
+            .take_while(|e| e.frame <= frame)
+            .count();
+        *next = start + due;
+        &self.events[start..*next]
+    }
+
+    /// Puts the emulator in the state the movie was recorded in. The emulator has to be
+    /// running the same ROM on the same platform.
+    pub fn prepare(&self, emu: &mut Emulator) -> Result<()> {
+        if emu.rom_hash() != self.rom_hash {
+            return Err(Error::InvalidMovie(format!(
+                "recorded with ROM {}, not {}",
+                self.rom_hash,
+                emu.rom_hash()
+            )));
+        }
+        if emu.platform() != self.platform {
+            return Err(Error::InvalidMovie(format!(
+                "recorded on platform {}, not {}",
+                self.platform,
+                emu.platform()
+            )));
+        }
+
+        emu.set_quirks(self.quirks);
+        emu.seed_rng(self.seed);
+        // user flags saved by earlier sessions would make the replay differ
+        emu.forget_flags();
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Movie> {
+        Movie::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn parse(data: &str) -> Result<Movie> {
+        let invalid = |line: &str| Error::InvalidMovie(format!("cannot read \"{}\"", line));
+        let mut lines = data.lines().map(str::trim).filter(|l| !l.is_empty());
+        if lines.next() != Some(MAGIC) {
+            return Err(Error::InvalidMovie("not a movie file".to_string()));
+        }
+
+        let mut rom_hash = None;
+        let mut platform = None;
+        let mut quirks = None;
+        let mut seed = None;
+        let mut speed = None;
+        let mut frames = None;
+        let mut events = Vec::new();
+
+        for line in lines {
+            let (name, value) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            match name {
+                "rom" => rom_hash = Some(value.to_string()),
+                "platform" => platform = Platform::from_name(value),
+                "quirks" => quirks = Some(value),
+                "seed" => seed = value.parse().ok(),
+                "speed" => speed = value.parse().ok(),
+                "frames" => frames = value.parse().ok(),
+                _ => events.push(parse_event(line).ok_or_else(|| invalid(line))?),
+            }
+        }
+
+        let missing = |field: &str| Error::InvalidMovie(format!("{} missing", field));
+        let platform = platform.ok_or_else(|| missing("platform"))?;
+        let quirks = match quirks {
+            Some(settings) => platform.quirks().with_settings(settings).ok_or_else(|| {
+                Error::InvalidMovie(format!("cannot read quirks \"{}\"", settings))
+            })?,
+            None => platform.quirks(),
+        };
+        // hand-edited movies may list events out of order
+        events.sort_by_key(|e| e.frame);
+        Ok(Movie {
+            rom_hash: rom_hash.ok_or_else(|| missing("rom"))?,
+            platform,
+            quirks,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            speed: speed.unwrap_or(1),
+            frames: frames.ok_or_else(|| missing("frames"))?,
+            events,
+        })
+    }
+}
+
+impl std::fmt::Display for Movie {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", MAGIC)?;
+        writeln!(f, "rom {}", self.rom_hash)?;
+        writeln!(f, "platform {}", self.platform)?;
+        writeln!(f, "quirks {}", self.quirks)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "speed {}", self.speed)?;
+        writeln!(f, "frames {}", self.frames)?;
+        for e in self.events.iter() {
+            let action = if e.pressed { "press" } else { "release" };
+            writeln!(f, "{} {} {:X}", e.frame, action, e.key as usize)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_event(line: &str) -> Option<KeyEvent> {
+    let mut parts = line.split_whitespace();
+    let frame = parts.next()?.parse().ok()?;
+    let pressed = match parts.next()? {
+        "press" => true,
+        "release" => false,
+        _ => return None,
+    };
+    let key = implementation::parse_key(parts.next()?)?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(KeyEvent {
+        frame,
+        key,
+        pressed,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Edge;
+
+    fn movie() -> Movie {
+        let mut quirks = Platform::SuperChip11.quirks();
+        quirks.vertical_edge = Edge::Wrap;
+        Movie {
+            rom_hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            platform: Platform::SuperChip11,
+            quirks,
+            seed: 42,
+            speed: 10,
+            frames: 300,
+            events: vec![
+                KeyEvent {
+                    frame: 12,
+                    key: Input::Key5,
+                    pressed: true,
+                },
+                KeyEvent {
+                    frame: 20,
+                    key: Input::KeyA,
+                    pressed: false,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = movie().to_string();
+        assert!(text.starts_with("chip8-movie 1\nrom 0123"));
+        assert!(text.contains("vertical-edge=wrap"));
+        assert!(text.ends_with("speed 10\nframes 300\n12 press 5\n20 release A\n"));
+
+        assert_eq!(Movie::parse(&text).unwrap(), movie());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Movie::parse("").is_err());
+        assert!(Movie::parse("chip8-movie 1\nplatform chip8\nseed 1\nframes 1").is_err());
+
+        let text = movie().to_string();
+        assert!(Movie::parse(&text.replace("press 5", "press 10")).is_err());
+        assert!(Movie::parse(&text.replace("lores-dxy0=big", "lores-dxy0=huge")).is_err());
+    }
+
+    #[test]
+    fn test_take_events() {
+        let text = movie().to_string().replace("12 press 5\n", "") + "12 press 5\n";
+        let movie = Movie::parse(&text).unwrap();
+        assert_eq!(movie.events[0].frame, 12);
+
+        let mut next = 0;
+        assert!(movie.take_events(&mut next, 11).is_empty());
+        assert_eq!(movie.take_events(&mut next, 19), &movie.events[..1]);
+        assert!(movie.take_events(&mut next, 19).is_empty());
+        assert_eq!(movie.take_events(&mut next, 300), &movie.events[1..]);
+        assert_eq!(next, 2);
+    }
+
+    #[test]
+    fn test_prepare() {
+        let rom: &[u8] = &[0x12, 0x00];
+        let mut emu = Emulator::new(rom).unwrap();
+        let mut movie = Movie::new(&emu, 7, 1);
+        movie.quirks.vertical_edge = Edge::Wrap;
+
+        movie.prepare(&mut emu).unwrap();
+        assert_eq!(emu.quirks().vertical_edge, Edge::Wrap);
+
+        movie.rom_hash = "other".to_string();
+        assert!(movie.prepare(&mut emu).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use super::display::Edge;
+use std::fmt;
+
+/// The machine variant a ROM was written for. Each platform comes with a set of [`Quirks`]
+/// describing where its interpreter deviates from the others.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Platform {
+    /// CHIP-8 as this emulator has always run it: shifts operate on VX in place and
+    /// `FX55`/`FX65` leave I untouched, which is what most of the bundled ROMs expect.
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.0 for the HP48. `FX55`/`FX65` advance I by X, and `DXY0` in low
+    /// resolution draws an 8x16 sprite.
+    SuperChip10,
+    /// SUPER-CHIP 1.1. I is left unchanged by `FX55`/`FX65`, low resolution `DXY0` draws a
+    /// 16x16 sprite, scrolling in low resolution moves by half the requested amount (the
+    /// HP48 draws low resolution by doubling pixels), switching resolution keeps the screen
+    /// contents, and `DXYN` in high resolution sets VF to the number of rows that collided
+    /// or were clipped.
+    SuperChip11,
+    /// SUPER-CHIP as interpreted by modern implementations such as SCHPC and Octo. Scrolling
+    /// uses full low resolution pixels, switching resolution clears the screen, and VF is a
+    /// plain collision flag in both resolutions.
+    SuperChipModern,
+    /// MEGA-CHIP, a SUPER-CHIP 1.1 extension with a 256x192 indexed-colour mode.
+    MegaChip,
+}
+
+/// What `DXY0` draws in low resolution. In high resolution it always draws 16x16.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoresDxy0 {
+    Nothing,
+    Tall,
+    Big,
+}
+
+/// How `FX55`/`FX65` move I after transferring registers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum IndexIncrement {
+    Unchanged,
+    X,
+    XPlusOne,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quirks {
+    pub lores_dxy0: LoresDxy0,
+    pub load_store: IndexIncrement,
+    pub half_scroll_lores: bool,
+    pub clear_on_resolution_change: bool,
+    pub hires_collision_rows: bool,
+    pub horizontal_edge: Edge,
+    pub vertical_edge: Edge,
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "schip1.0" | "superchip1.0" => Some(Platform::SuperChip10),
+            "schip" | "schip1.1" | "superchip" | "superchip1.1" => Some(Platform::SuperChip11),
+            "schip-modern" | "schpc" | "octo" => Some(Platform::SuperChipModern),
+            "megachip" | "mega-chip" => Some(Platform::MegaChip),
+            _ => None,
+        }
+    }
+
+    // MEGA-CHIP addresses memory with a 24-bit I register.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::MegaChip => 0x100_0000,
+            _ => 4096,
+        }
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks {
+                lores_dxy0: LoresDxy0::Nothing,
+                load_store: IndexIncrement::Unchanged,
+                half_scroll_lores: false,
+                clear_on_resolution_change: true,
+                hires_collision_rows: false,
+                horizontal_edge: Edge::Clip,
+                vertical_edge: Edge::Clip,
+            },
+            Platform::SuperChip10 => Quirks {
+                lores_dxy0: LoresDxy0::Tall,
+                load_store: IndexIncrement::X,
+                half_scroll_lores: true,
+                clear_on_resolution_change: false,
+                hires_collision_rows: false,
+                horizontal_edge: Edge::Clip,
+                vertical_edge: Edge::Clip,
+            },
+            Platform::SuperChip11 | Platform::MegaChip => Quirks {
+                lores_dxy0: LoresDxy0::Big,
+                load_store: IndexIncrement::Unchanged,
+                half_scroll_lores: true,
+                clear_on_resolution_change: false,
+                hires_collision_rows: true,
+                horizontal_edge: Edge::Clip,
+                vertical_edge: Edge::Clip,
+            },
+            Platform::SuperChipModern => Quirks {
+                lores_dxy0: LoresDxy0::Big,
+                load_store: IndexIncrement::Unchanged,
+                half_scroll_lores: false,
+                clear_on_resolution_change: true,
+                hires_collision_rows: false,
+                horizontal_edge: Edge::Clip,
+                vertical_edge: Edge::Clip,
+            },
+        }
+    }
+}
+
+impl Quirks {
+    /// Applies settings written as `name=value` pairs separated by spaces or commas, such as
+    /// `horizontal-edge=wrap,vertical-edge=wrap` or the output of `Display`. Returns `None` if
+    /// any of them is not understood.
+    pub fn with_settings(mut self, settings: &str) -> Option<Quirks> {
+        let separator = |c: char| c == ',' || c.is_whitespace();
+        for setting in settings.split(separator).filter(|s| !s.is_empty()) {
+            let (name, value) = setting.split_once('=')?;
+            let flag = || value.parse::<bool>().ok();
+            let edge = || match value {
+                "clip" => Some(Edge::Clip),
+                "wrap" => Some(Edge::Wrap),
+                _ => None,
+            };
+            match name {
+                "lores-dxy0" => {
+                    self.lores_dxy0 = match value {
+                        "nothing" => LoresDxy0::Nothing,
+                        "tall" => LoresDxy0::Tall,
+                        "big" => LoresDxy0::Big,
+                        _ => return None,
+                    }
+                }
+                "load-store" => {
+                    self.load_store = match value {
+                        "unchanged" => IndexIncrement::Unchanged,
+                        "x" => IndexIncrement::X,
+                        "x+1" => IndexIncrement::XPlusOne,
+                        _ => return None,
+                    }
+                }
+                "half-scroll-lores" => self.half_scroll_lores = flag()?,
+                "clear-on-resolution-change" => self.clear_on_resolution_change = flag()?,
+                "hires-collision-rows" => self.hires_collision_rows = flag()?,
+                "horizontal-edge" => self.horizontal_edge = edge()?,
+                "vertical-edge" => self.vertical_edge = edge()?,
+                _ => return None,
+            }
+        }
+        Some(self)
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::default().quirks()
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => f.write_str("chip8"),
+            Platform::SuperChip10 => f.write_str("schip1.0"),
+            Platform::SuperChip11 => f.write_str("schip1.1"),
+            Platform::SuperChipModern => f.write_str("schip-modern"),
+            Platform::MegaChip => f.write_str("megachip"),
+        }
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let edge = |edge: Edge| match edge {
+            Edge::Clip => "clip",
+            Edge::Wrap => "wrap",
+        };
+        let lores_dxy0 = match self.lores_dxy0 {
+            LoresDxy0::Nothing => "nothing",
+            LoresDxy0::Tall => "tall",
+            LoresDxy0::Big => "big",
+        };
+        let load_store = match self.load_store {
+            IndexIncrement::Unchanged => "unchanged",
+            IndexIncrement::X => "x",
+            IndexIncrement::XPlusOne => "x+1",
+        };
+
+        write!(
+            f,
+            "lores-dxy0={} load-store={} half-scroll-lores={} clear-on-resolution-change={} \
+             hires-collision-rows={} horizontal-edge={} vertical-edge={}",
+            lores_dxy0,
+            load_store,
+            self.half_scroll_lores,
+            self.clear_on_resolution_change,
+            self.hires_collision_rows,
+            edge(self.horizontal_edge),
+            edge(self.vertical_edge),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_with_settings() {
+        let quirks = Platform::Chip8
+            .quirks()
+            .with_settings("horizontal-edge=wrap, load-store=x+1 half-scroll-lores=true")
+            .unwrap();
+        assert_eq!(quirks.horizontal_edge, Edge::Wrap);
+        assert_eq!(quirks.vertical_edge, Edge::Clip);
+        assert_eq!(quirks.load_store, IndexIncrement::XPlusOne);
+        assert!(quirks.half_scroll_lores);
+        assert_eq!(Quirks::default().with_settings(""), Some(Quirks::default()));
+
+        assert!(Quirks::default()
+            .with_settings("vertical-edge=bounce")
+            .is_none());
+        assert!(Quirks::default().with_settings("wobble=true").is_none());
+        assert!(Quirks::default()
+            .with_settings("half-scroll-lores")
+            .is_none());
+    }
+
+    #[test]
+    fn test_display() {
+        let mut quirks = Platform::SuperChip10.quirks();
+        quirks.vertical_edge = Edge::Wrap;
+        let text = quirks.to_string();
+        assert!(text.starts_with("lores-dxy0=tall load-store=x "));
+        assert!(text.ends_with("horizontal-edge=clip vertical-edge=wrap"));
+        assert_eq!(Quirks::default().with_settings(&text), Some(quirks));
+    }
+}
@@ -0,0 +1,104 @@
+use super::common::Result;
+use log::{debug, warn};
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// SUPER-CHIP has 8 user flags, saved and restored by `FX75`/`FX85` with X up to 7. XO-CHIP
+// extends this to 16.
+pub const RPL_COUNT: usize = 8;
+
+/// Identifies a ROM by the SHA-1 digest of its contents.
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+/// Directory used for persistent emulator data. `CHIP8_DATA_DIR` takes precedence over the
+/// XDG data directory.
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("CHIP8_DATA_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+
+    if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        return Some(PathBuf::from(dir).join("chip8"));
+    }
+
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share/chip8"))
+}
+
+/// Keeps the SUPER-CHIP RPL user flags of a single ROM on disk so that high scores survive
+/// restarts.
+pub struct FlagStore {
+    path: PathBuf,
+}
+
+impl FlagStore {
+    pub fn new(path: PathBuf) -> Self {
+        FlagStore { path }
+    }
+
+    pub fn for_rom(hash: &str) -> Option<Self> {
+        data_dir().map(|dir| FlagStore::new(dir.join("rpl").join(format!("{}.flags", hash))))
+    }
+
+    pub fn load(&self) -> Result<[u8; RPL_COUNT]> {
+        let mut flags = [0u8; RPL_COUNT];
+        match fs::read(&self.path) {
+            Ok(data) => {
+                debug!("Loaded RPL flags from {}", self.path.display());
+                flags.iter_mut().zip(data.iter()).for_each(|(f, d)| *f = *d);
+                Ok(flags)
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(flags),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save(&self, flags: &[u8; RPL_COUNT]) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, flags)?;
+        debug!("Saved RPL flags to {}", self.path.display());
+        Ok(())
+    }
+
+    // Persistence is best-effort: a read-only disk shouldn't stop the game.
+    pub fn save_or_warn(&self, flags: &[u8; RPL_COUNT]) {
+        if let Err(err) = self.save(flags) {
+            warn!(
+                "Failed to save RPL flags to {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(rom_hash(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[test]
+    fn test_flag_store_roundtrip() {
+        let path = env::temp_dir().join(format!("chip8-rpl-{}", std::process::id()));
+        let store = FlagStore::new(path.join("test.flags"));
+        assert_eq!(store.load().unwrap(), [0u8; RPL_COUNT]);
+
+        let mut flags = [0u8; RPL_COUNT];
+        flags[0] = 42;
+        flags[RPL_COUNT - 1] = 7;
+        store.save(&flags).unwrap();
+        assert_eq!(store.load().unwrap(), flags);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+use crate::emulator::Framebuffer;
+use std::collections::VecDeque;
+
+/// How frames are combined before they reach the renderer.
+///
+/// CHIP-8 programs move sprites by XOR-erasing and redrawing them, so a renderer that shows
+/// whatever was last drawn catches sprites while they are erased.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Mode {
+    /// Show the latest framebuffer as soon as it is drawn.
+    #[default]
+    Off,
+    /// Only update the picture once per frame, at vertical blank.
+    VBlank,
+    /// Light every pixel that was lit at any point during the last N frames.
+    Or(usize),
+    /// Let pixels fade out like phosphor. The brightness left after each frame is multiplied
+    /// by the persistence, between 0.0 (no afterglow) and 1.0 (never fades).
+    Decay(f32),
+}
+
+impl Mode {
+    /// Parses `off`, `vblank`, `or:N` or `decay:P`.
+    pub fn from_name(name: &str) -> Option<Mode> {
+        let mut parts = name.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("off"), None) => Some(Mode::Off),
+            (Some("vblank"), None) => Some(Mode::VBlank),
+            (Some("or"), Some(n)) => n.parse().ok().filter(|n| *n > 0).map(Mode::Or),
+            (Some("decay"), Some(p)) => p
+                .parse()
+                .ok()
+                .filter(|p: &f32| (0.0..=1.0).contains(p))
+                .map(Mode::Decay),
+            _ => None,
+        }
+    }
+}
+
+/// A framebuffer after anti-flicker processing. For every pixel it holds the planes that were
+/// last lit on it and the brightness it should be drawn with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Blended {
+    pub width: usize,
+    pub height: usize,
+    pub planes: Vec<u8>,
+    pub intensity: Vec<f32>,
+}
+
+impl Blended {
+    fn from_bits(width: usize, height: usize, bits: &[u8]) -> Self {
+        Blended {
+            width,
+            height,
+            planes: bits.to_vec(),
+            intensity: bits
+                .iter()
+                .map(|b| if *b == 0 { 0.0 } else { 1.0 })
+                .collect(),
+        }
+    }
+}
+
+impl From<&Framebuffer> for Blended {
+    fn from(fb: &Framebuffer) -> Self {
+        Blended::from_bits(fb.width(), fb.height(), fb.bits())
+    }
+}
+
+/// Sits between the emulator and a renderer. Every `Step::Draw` is pushed into it and
+/// `vblank` is called once per 60 Hz frame; `output` then returns what should be shown.
+pub struct AntiFlicker {
+    mode: Mode,
+    latest: Option<Framebuffer>,
+    frame_bits: Vec<u8>, // everything lit since the last vertical blank
+    history: VecDeque<Vec<u8>>,
+    output: Blended,
+}
+
+impl AntiFlicker {
+    pub fn new(mode: Mode) -> Self {
+        AntiFlicker {
+            mode,
+            latest: None,
+            frame_bits: Vec::new(),
+            history: VecDeque::new(),
+            output: Blended::default(),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.history.clear();
+    }
+
+    pub fn push(&mut self, fb: Framebuffer) {
+        let resized = match self.latest.as_ref() {
+            Some(l) => l.width() != fb.width() || l.height() != fb.height(),
+            None => true,
+        };
+        if resized {
+            self.frame_bits = vec![0u8; fb.width() * fb.height()];
+            self.history.clear();
+            self.output = Blended::from_bits(fb.width(), fb.height(), &self.frame_bits);
+        }
+
+        self.frame_bits
+            .iter_mut()
+            .zip(fb.bits().iter())
+            .for_each(|(acc, b)| *acc |= *b);
+        self.latest = Some(fb);
+    }
+
+    pub fn vblank(&mut self) {
+        let latest = match self.latest.as_ref() {
+            Some(fb) => fb,
+            None => return,
+        };
+        let (width, height) = (latest.width(), latest.height());
+
+        // Whatever is still on screen at the blank was lit during this frame too.
+        self.frame_bits
+            .iter_mut()
+            .zip(latest.bits().iter())
+            .for_each(|(acc, b)| *acc |= *b);
+
+        match self.mode {
+            Mode::Off | Mode::VBlank => {
+                self.output = Blended::from_bits(width, height, latest.bits());
+            }
+            Mode::Or(n) => {
+                self.history.push_back(self.frame_bits.clone());
+                while self.history.len() > n {
+                    self.history.pop_front();
+                }
+
+                let mut bits = vec![0u8; width * height];
+                for frame in self.history.iter() {
+                    bits.iter_mut()
+                        .zip(frame.iter())
+                        .for_each(|(acc, b)| *acc |= *b);
+                }
+                self.output = Blended::from_bits(width, height, &bits);
+            }
+            Mode::Decay(persistence) => {
+                let output = &mut self.output;
+                for (i, b) in self.frame_bits.iter().enumerate() {
+                    if *b != 0 {
+                        output.planes[i] = *b;
+                        output.intensity[i] = 1.0;
+                    } else {
+                        output.intensity[i] *= persistence;
+                    }
+                }
+            }
+        }
+
+        self.frame_bits.iter_mut().for_each(|b| *b = 0);
+    }
+
+    pub fn output(&self) -> Blended {
+        match (self.mode, self.latest.as_ref()) {
+            (Mode::Off, Some(fb)) => Blended::from(fb),
+            _ => self.output.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn framebuffer(lit: &[(usize, usize)]) -> Framebuffer {
+        let mut fb = Framebuffer::new(4, 2, 1);
+        lit.iter().for_each(|(x, y)| fb.set(*x, *y, 1));
+        fb
+    }
+
+    #[test]
+    fn test_mode_from_name() {
+        assert_eq!(Mode::from_name("off"), Some(Mode::Off));
+        assert_eq!(Mode::from_name("vblank"), Some(Mode::VBlank));
+        assert_eq!(Mode::from_name("or:3"), Some(Mode::Or(3)));
+        assert_eq!(Mode::from_name("decay:0.5"), Some(Mode::Decay(0.5)));
+        assert_eq!(Mode::from_name("or:0"), None);
+        assert_eq!(Mode::from_name("decay:2"), None);
+        assert_eq!(Mode::from_name("blur"), None);
+    }
+
+    #[test]
+    fn test_vblank_ignores_intermediate_draws() {
+        let mut af = AntiFlicker::new(Mode::VBlank);
+        af.push(framebuffer(&[(0, 0)]));
+        af.vblank();
+        af.push(framebuffer(&[]));
+        assert_eq!(af.output().intensity[0], 1.0);
+
+        af.vblank();
+        assert_eq!(af.output().intensity[0], 0.0);
+    }
+
+    #[test]
+    fn test_or_keeps_erased_sprites() {
+        let mut af = AntiFlicker::new(Mode::Or(2));
+        af.push(framebuffer(&[(0, 0)]));
+        af.push(framebuffer(&[]));
+        af.push(framebuffer(&[(1, 0)]));
+        af.vblank();
+        assert_eq!(af.output().intensity[..2], [1.0, 1.0]);
+
+        af.push(framebuffer(&[]));
+        af.vblank();
+        assert_eq!(af.output().intensity[..2], [1.0, 1.0]);
+
+        af.vblank();
+        assert_eq!(af.output().intensity[..2], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_decay_fades_out() {
+        let mut af = AntiFlicker::new(Mode::Decay(0.5));
+        af.push(framebuffer(&[(2, 1)]));
+        af.vblank();
+        assert_eq!(af.output().intensity[6], 1.0);
+
+        af.push(framebuffer(&[]));
+        af.vblank();
+        assert_eq!(af.output().intensity[6], 0.5);
+        assert_eq!(af.output().planes[6], 1);
+
+        af.vblank();
+        assert_eq!(af.output().intensity[6], 0.25);
+    }
+}
@@ -0,0 +1,653 @@
+use crate::emulator::common::Result;
+use crate::emulator::Sample;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use log::warn;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+/// Samples per second produced by every sink.
+pub const SAMPLE_RATE: u32 = 44100;
+/// Timer ticks per second; each tick covers `SAMPLE_RATE / TICK_RATE` samples.
+pub const TICK_RATE: u32 = 60;
+pub const SAMPLES_PER_TICK: usize = (SAMPLE_RATE / TICK_RATE) as usize;
+
+const NOISE_SEED: u32 = 0x2545_F491;
+const PLAYER: &str = "aplay";
+const PLAYER_QUEUE: usize = 8; // ticks waiting for the player, about 130 ms
+
+/// Somewhere the buzzer can be heard. The session turns the tone on and off as the emulator
+/// reports `SoundOn` and `SoundOff` steps, and advances the sink by one timer tick per frame.
+pub trait AudioSink {
+    fn set_tone(&mut self, on: bool);
+
+    /// Changes how the tone sounds from the next tick on.
+    fn set_buzzer(&mut self, _buzzer: Buzzer) {}
+
+    /// Starts playing a MEGA-CHIP sample over the tone, or stops the one playing on `None`.
+    fn set_sample(&mut self, _sample: Option<Sample>) {}
+
+    /// Produces the sound of one timer tick.
+    fn tick(&mut self) -> Result<()>;
+
+    /// Flushes anything still buffered.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays nothing.
+#[derive(Debug, Default)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn set_tone(&mut self, _on: bool) {}
+
+    fn tick(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The shape of the buzzer's tone.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+    /// White noise, changing value twice per period of the frequency.
+    Noise,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [
+        Waveform::Square,
+        Waveform::Sine,
+        Waveform::Triangle,
+        Waveform::Noise,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "noise" => Some(Waveform::Noise),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Noise => "noise",
+        }
+    }
+
+    /// The waveform after this one, wrapping around.
+    pub fn next(self) -> Waveform {
+        let i = Waveform::ALL.iter().position(|w| *w == self).unwrap_or(0);
+        Waveform::ALL[(i + 1) % Waveform::ALL.len()]
+    }
+}
+
+/// How the buzzer sounds. The envelope fades the tone in and out over `attack` and `release`
+/// seconds so that switching it does not click.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Buzzer {
+    pub waveform: Waveform,
+    pub frequency: f32, // Hz
+    pub volume: f32,    // 0 to 1
+    pub attack: f32,
+    pub release: f32,
+}
+
+impl Default for Buzzer {
+    fn default() -> Self {
+        Buzzer {
+            waveform: Waveform::default(),
+            frequency: 440.0,
+            volume: 0.25,
+            attack: 0.005,
+            release: 0.005,
+        }
+    }
+}
+
+impl Buzzer {
+    pub const MIN_FREQUENCY: f32 = 20.0;
+    pub const MAX_FREQUENCY: f32 = (SAMPLE_RATE / 2) as f32;
+
+    /// Checks that every setting is in range, describing the first one that is not.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if !(Buzzer::MIN_FREQUENCY..=Buzzer::MAX_FREQUENCY).contains(&self.frequency) {
+            return Err(format!(
+                "frequency {} Hz is outside {} to {} Hz",
+                self.frequency,
+                Buzzer::MIN_FREQUENCY,
+                Buzzer::MAX_FREQUENCY
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.volume) {
+            return Err(format!("volume {} is outside 0 to 1", self.volume));
+        }
+        if !(self.attack >= 0.0 && self.release >= 0.0) {
+            return Err("attack and release cannot be negative".to_string());
+        }
+        Ok(())
+    }
+
+    /// Changes the volume by `delta`, staying within 0 to 1.
+    pub fn adjust_volume(&mut self, delta: f32) {
+        self.volume = (self.volume + delta).clamp(0.0, 1.0);
+    }
+
+    /// Moves the frequency by a number of semitones, staying within the valid range.
+    pub fn adjust_pitch(&mut self, semitones: f32) {
+        self.frequency = (self.frequency * 2f32.powf(semitones / 12.0))
+            .clamp(Buzzer::MIN_FREQUENCY, Buzzer::MAX_FREQUENCY);
+    }
+}
+
+/// Turns the buzzer on and off into samples.
+#[derive(Debug)]
+pub struct Tone {
+    buzzer: Buzzer,
+    on: bool,
+    level: f32, // envelope, 0 to 1
+    phase: f64, // position in the period, 0 to 1
+    // the phase is worked out from a sample count rather than added up, so it does not drift
+    phase_start: f64,
+    count: u64,
+    noise: u32,
+    noise_value: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone::new(Buzzer::default())
+    }
+}
+
+impl Tone {
+    pub fn new(buzzer: Buzzer) -> Self {
+        let mut tone = Tone {
+            buzzer,
+            on: false,
+            level: 0.0,
+            phase: 0.0,
+            phase_start: 0.0,
+            count: 0,
+            noise: NOISE_SEED,
+            noise_value: 0.0,
+        };
+        tone.next_noise();
+        tone
+    }
+
+    pub fn set(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    pub fn set_buzzer(&mut self, buzzer: Buzzer) {
+        if buzzer.frequency != self.buzzer.frequency {
+            self.phase_start = self.phase;
+            self.count = 0;
+        }
+        self.buzzer = buzzer;
+    }
+
+    /// Appends the samples of one timer tick.
+    pub fn tick(&mut self, out: &mut Vec<i16>) {
+        let rate = SAMPLE_RATE as f64;
+        let rise = envelope_step(self.buzzer.attack);
+        let fall = envelope_step(self.buzzer.release);
+
+        for _i in 0..SAMPLES_PER_TICK {
+            self.level = if self.on {
+                (self.level + rise).min(1.0)
+            } else {
+                (self.level - fall).max(0.0)
+            };
+
+            if self.level == 0.0 {
+                // restart the wave so every beep begins the same way
+                self.phase = 0.0;
+                self.phase_start = 0.0;
+                self.count = 0;
+                out.push(0);
+                continue;
+            }
+
+            let value = self.wave();
+            let amplitude = value * self.level * self.buzzer.volume * i16::MAX as f32;
+            out.push(amplitude.round() as i16);
+
+            let previous = self.phase;
+            self.count += 1;
+            self.phase = (self.phase_start
+                + self.count as f64 * self.buzzer.frequency as f64 / rate)
+                .fract();
+            if (previous < 0.5) != (self.phase < 0.5) {
+                self.next_noise();
+            }
+        }
+    }
+
+    fn wave(&self) -> f32 {
+        let phase = self.phase as f32;
+        match self.buzzer.waveform {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.25).abs().min((phase - 1.25).abs()),
+            Waveform::Noise => self.noise_value,
+        }
+    }
+
+    // xorshift, so the noise is the same on every run
+    fn next_noise(&mut self) {
+        let mut x = self.noise;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.noise = x;
+        self.noise_value = x as f32 / u32::MAX as f32 * 2.0 - 1.0;
+    }
+}
+
+/// Plays the digitised sound of a MEGA-CHIP program at its own rate, mixed over the tone.
+#[derive(Debug)]
+pub struct SamplePlayer {
+    sample: Option<Sample>,
+    position: f64, // in samples of the source
+    volume: f32,
+}
+
+impl Default for SamplePlayer {
+    fn default() -> Self {
+        SamplePlayer {
+            sample: None,
+            position: 0.0,
+            volume: Buzzer::default().volume,
+        }
+    }
+}
+
+impl SamplePlayer {
+    pub fn set(&mut self, sample: Option<Sample>) {
+        self.sample = sample.filter(|s| s.rate > 0 && !s.data.is_empty());
+        self.position = 0.0;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    /// Adds the next stretch of the sample to the samples of one timer tick.
+    pub fn tick(&mut self, out: &mut [i16]) {
+        let sample = match self.sample.as_ref() {
+            Some(sample) => sample,
+            None => return,
+        };
+        let step = sample.rate as f64 / SAMPLE_RATE as f64;
+        let len = sample.data.len() as f64;
+
+        for value in out.iter_mut() {
+            if self.position >= len {
+                if !sample.looping {
+                    self.sample = None;
+                    return;
+                }
+                self.position %= len;
+            }
+
+            // the data is unsigned, with silence at 128
+            let level = (sample.data[self.position as usize] as f32 - 128.0) / 128.0;
+            let amplitude = level * self.volume * i16::MAX as f32;
+            *value = value.saturating_add(amplitude.round() as i16);
+            self.position += step;
+        }
+    }
+}
+
+/// How much the envelope moves per sample to go from silent to full in `seconds`.
+fn envelope_step(seconds: f32) -> f32 {
+    if seconds <= 0.0 {
+        1.0
+    } else {
+        1.0 / (seconds * SAMPLE_RATE as f32)
+    }
+}
+
+/// Writes the sound as a 16-bit mono PCM WAV file. The sizes in the header are filled in by
+/// `finish`.
+pub struct WavSink<W: Write + Seek> {
+    writer: W,
+    tone: Tone,
+    sample: SamplePlayer,
+    samples: u32,
+    buffer: Vec<i16>,
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W) -> Result<Self> {
+        write_header(&mut writer, 0)?;
+        Ok(WavSink {
+            writer,
+            tone: Tone::default(),
+            sample: SamplePlayer::default(),
+            samples: 0,
+            buffer: Vec::with_capacity(SAMPLES_PER_TICK),
+        })
+    }
+
+    /// Number of samples written so far.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn set_tone(&mut self, on: bool) {
+        self.tone.set(on);
+    }
+
+    fn set_buzzer(&mut self, buzzer: Buzzer) {
+        self.tone.set_buzzer(buzzer);
+        self.sample.set_volume(buzzer.volume);
+    }
+
+    fn set_sample(&mut self, sample: Option<Sample>) {
+        self.sample.set(sample);
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        self.buffer.clear();
+        self.tone.tick(&mut self.buffer);
+        self.sample.tick(&mut self.buffer);
+        write_samples(&mut self.writer, &self.buffer)?;
+        self.samples += self.buffer.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.writer, self.samples)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_header<W: Write>(out: &mut W, samples: u32) -> io::Result<()> {
+    let data_size = samples * 2;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // mono
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // bytes per second
+    out.write_all(&2u16.to_le_bytes())?; // bytes per frame
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
+
+fn write_samples<W: Write>(out: &mut W, samples: &[i16]) -> io::Result<()> {
+    let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    out.write_all(&bytes)
+}
+
+/// Streams raw samples to `aplay`, so it only works on Linux with alsa-utils installed. The
+/// samples are written to the player on a thread of their own; when it falls behind, whole ticks
+/// are dropped rather than holding up the emulator. If the player cannot be started or goes
+/// away, the sink warns once and stays silent.
+pub struct PlayerSink {
+    chunks: Option<Sender<Vec<i16>>>,
+    tone: Tone,
+    sample: SamplePlayer,
+    buffer: Vec<i16>,
+}
+
+impl PlayerSink {
+    pub fn new() -> Self {
+        let rate = SAMPLE_RATE.to_string();
+        let player = Command::new(PLAYER)
+            .args(["-q", "-t", "raw", "-f", "S16_LE", "-c", "1", "-r", &rate])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let chunks = match player {
+            Ok(child) => {
+                let (sender, receiver) = crossbeam_channel::bounded(PLAYER_QUEUE);
+                thread::spawn(move || play(child, receiver));
+                Some(sender)
+            }
+            Err(e) => {
+                warn!("no sound, could not start {}: {}", PLAYER, e);
+                None
+            }
+        };
+
+        PlayerSink {
+            chunks,
+            tone: Tone::default(),
+            sample: SamplePlayer::default(),
+            buffer: Vec::with_capacity(SAMPLES_PER_TICK),
+        }
+    }
+}
+
+impl Default for PlayerSink {
+    fn default() -> Self {
+        PlayerSink::new()
+    }
+}
+
+impl AudioSink for PlayerSink {
+    fn set_tone(&mut self, on: bool) {
+        self.tone.set(on);
+    }
+
+    fn set_buzzer(&mut self, buzzer: Buzzer) {
+        self.tone.set_buzzer(buzzer);
+        self.sample.set_volume(buzzer.volume);
+    }
+
+    fn set_sample(&mut self, sample: Option<Sample>) {
+        self.sample.set(sample);
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        self.buffer.clear();
+        self.tone.tick(&mut self.buffer);
+        self.sample.tick(&mut self.buffer);
+
+        if let Some(chunks) = self.chunks.as_ref() {
+            match chunks.try_send(self.buffer.clone()) {
+                Ok(()) | Err(TrySendError::Full(_)) => {}
+                Err(TrySendError::Disconnected(_)) => self.chunks = None,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Feeds the player until the sink goes away or the player stops taking samples.
+fn play(mut player: Child, chunks: Receiver<Vec<i16>>) {
+    if let Some(mut stdin) = player.stdin.take() {
+        for chunk in chunks.iter() {
+            if let Err(e) = write_samples(&mut stdin, &chunk) {
+                warn!("no sound, {} stopped: {}", PLAYER, e);
+                break;
+            }
+        }
+    }
+    let _ = player.kill();
+    let _ = player.wait();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sharp(waveform: Waveform) -> Buzzer {
+        Buzzer {
+            waveform,
+            frequency: 441.0, // 100 samples per period
+            volume: 1.0,
+            attack: 0.0,
+            release: 0.0,
+        }
+    }
+
+    fn one_tick(tone: &mut Tone) -> Vec<i16> {
+        let mut samples = Vec::new();
+        tone.tick(&mut samples);
+        assert_eq!(samples.len(), SAMPLES_PER_TICK);
+        samples
+    }
+
+    #[test]
+    fn test_waveforms() {
+        let mut tone = Tone::new(sharp(Waveform::Square));
+        assert!(one_tick(&mut tone).iter().all(|s| *s == 0));
+
+        tone.set(true);
+        let samples = one_tick(&mut tone);
+        assert_eq!(
+            (samples[0], samples[50], samples[100]),
+            (32767, -32767, 32767)
+        );
+
+        let mut tone = Tone::new(sharp(Waveform::Sine));
+        tone.set(true);
+        let samples = one_tick(&mut tone);
+        assert_eq!((samples[0], samples[25], samples[75]), (0, 32767, -32767));
+
+        let mut tone = Tone::new(sharp(Waveform::Triangle));
+        tone.set(true);
+        let samples = one_tick(&mut tone);
+        assert_eq!(
+            (samples[0], samples[25], samples[50], samples[75]),
+            (0, 32767, 0, -32767)
+        );
+
+        let mut tone = Tone::new(sharp(Waveform::Noise));
+        tone.set(true);
+        let samples = one_tick(&mut tone);
+        assert_eq!(samples[0], samples[49]);
+        assert_ne!(samples[49], samples[50]);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut tone = Tone::new(Buzzer {
+            attack: 0.001, // 44 samples
+            release: 0.001,
+            ..sharp(Waveform::Square)
+        });
+        tone.set(true);
+        let samples = one_tick(&mut tone);
+        assert!(samples[0] > 0 && samples[0] < 1000);
+        assert!(samples[20] < samples[40]);
+        assert_eq!(samples[45], 32767);
+
+        tone.set(false);
+        let samples = one_tick(&mut tone);
+        assert!(samples[0] != 0);
+        assert!(samples[50..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn test_buzzer_settings() {
+        assert_eq!(Waveform::from_name("triangle"), Some(Waveform::Triangle));
+        assert_eq!(Waveform::from_name("saw"), None);
+        assert_eq!(Waveform::Noise.next(), Waveform::Square);
+
+        let mut buzzer = Buzzer::default();
+        assert!(buzzer.validate().is_ok());
+        buzzer.adjust_pitch(12.0);
+        assert_eq!(buzzer.frequency, 880.0);
+        buzzer.adjust_volume(2.0);
+        assert_eq!(buzzer.volume, 1.0);
+
+        buzzer.frequency = 5.0;
+        assert!(buzzer.validate().is_err());
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut player = SamplePlayer::default();
+        player.set_volume(1.0);
+        // a quarter of the output rate, so every value lasts four samples
+        player.set(Some(Sample {
+            rate: (SAMPLE_RATE / 4) as u16,
+            data: vec![255, 128, 0],
+            looping: false,
+        }));
+
+        let mut out = vec![0i16; SAMPLES_PER_TICK];
+        player.tick(&mut out);
+        assert_eq!(out[..4], [32511; 4]);
+        assert_eq!(out[4..8], [0; 4]);
+        assert_eq!(out[8..12], [-32767; 4]);
+        assert!(out[12..].iter().all(|s| *s == 0));
+
+        player.set(Some(Sample {
+            rate: (SAMPLE_RATE / 4) as u16,
+            data: vec![255, 0],
+            looping: true,
+        }));
+        let mut out = vec![16384i16; SAMPLES_PER_TICK];
+        player.tick(&mut out);
+        assert_eq!((out[0], out[4], out[8]), (32767, -16383, 32767));
+
+        player.set(None);
+        let mut out = vec![0i16; SAMPLES_PER_TICK];
+        player.tick(&mut out);
+        assert!(out.iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn test_wav() {
+        let mut sink = WavSink::new(Cursor::new(Vec::new())).unwrap();
+        sink.set_buzzer(sharp(Waveform::Square));
+        sink.tick().unwrap();
+        sink.set_tone(true);
+        sink.tick().unwrap();
+        sink.finish().unwrap();
+        assert_eq!(sink.samples(), 2 * SAMPLES_PER_TICK as u32);
+
+        let wav = sink.into_inner().into_inner();
+        let data_size = 4 * SAMPLES_PER_TICK as u32;
+        assert_eq!(wav.len(), 44 + data_size as usize);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav[4..8], (36 + data_size).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[24..28], SAMPLE_RATE.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[40..44], data_size.to_le_bytes());
+
+        // silent first tick, then the tone
+        assert_eq!(wav[44..46], [0, 0]);
+        let second = 44 + 2 * SAMPLES_PER_TICK;
+        assert_eq!(wav[second..second + 2], 32767i16.to_le_bytes());
+    }
+}
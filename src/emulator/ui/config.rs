@@ -0,0 +1,407 @@
+use crate::emulator::common::{Error, Result};
+use crate::emulator::implementation::parse_key;
+use crate::emulator::ui::audio::{Buzzer, Waveform};
+use crate::emulator::ui::input::{Autofire, Macro};
+use crate::emulator::ui::keymap::Keymap;
+use crate::emulator::ui::palette::{self, Palette};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings read from `config.toml`. Anything left out falls back to the built-in defaults, and
+/// command line arguments take precedence over the file.
+///
+/// ```toml
+/// platform = "schip1.1"
+/// anti-flicker = "or:2"
+/// palette = "paper"
+/// scaling = "fractional"
+/// fullscreen = true
+/// keypad = true
+/// effects = "scanlines,bloom"
+/// speed = 10
+/// keymap = "azerty"
+///
+/// # a keymap maps CHIP-8 keys to host keys, starting from an optional preset
+/// [keymaps.arrows]
+/// base = "qwerty"
+/// 5 = ["w", "up"]
+/// 7 = ["a", "left"]
+/// 8 = ["s", "down"]
+/// 9 = ["d", "right"]
+///
+/// # keymaps for particular ROMs, by file name
+/// [rom-keymaps]
+/// INVADERS = "arrows"
+///
+/// # host keys that press a CHIP-8 key repeatedly, `rate` times a second
+/// [autofire]
+/// rate = 12
+/// space = "5"
+///
+/// # key presses played by a host key, see `Macro` for the steps
+/// [macros.shuffle]
+/// key = "m"
+/// sequence = "4 4 8 wait:10 6"
+///
+/// [buzzer]
+/// waveform = "triangle"
+/// frequency = 330
+/// volume = 0.2
+/// attack = 10  # milliseconds
+/// release = 30
+///
+/// [palettes.paper]
+/// background = "#F0EAD6"
+/// planes = ["#202020", "#A05030", "#603020"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub platform: Option<String>,
+    pub anti_flicker: Option<String>,
+    pub palette: Option<String>,
+    pub scaling: Option<String>,
+    pub fullscreen: Option<bool>,
+    pub keypad: Option<bool>,
+    pub effects: Option<String>,
+    pub speed: Option<usize>,
+    pub keymap: Option<String>,
+    pub keymaps: BTreeMap<String, KeymapConfig>,
+    pub rom_keymaps: BTreeMap<String, String>,
+    pub autofire: AutofireConfig,
+    pub macros: BTreeMap<String, MacroConfig>,
+    pub buzzer: BuzzerConfig,
+    pub palettes: BTreeMap<String, PaletteConfig>,
+}
+
+/// A keymap table. Every other entry binds a CHIP-8 key, written as a hex digit, to a list of
+/// host keys, replacing what the base keymap had for it.
+#[derive(Debug, Default, Deserialize)]
+pub struct KeymapConfig {
+    pub base: Option<String>,
+    #[serde(flatten)]
+    pub keys: BTreeMap<String, Vec<String>>,
+}
+
+/// The `[autofire]` table. Every entry apart from `rate` binds a host key to the CHIP-8 key,
+/// written as a hex digit, it fires.
+#[derive(Debug, Default, Deserialize)]
+pub struct AutofireConfig {
+    pub rate: Option<f64>,
+    #[serde(flatten)]
+    pub keys: BTreeMap<String, String>,
+}
+
+/// A `[macros.NAME]` table: the host key that plays the macro and its steps.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacroConfig {
+    pub key: String,
+    pub sequence: String,
+}
+
+/// The `[buzzer]` table. Attack and release are in milliseconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuzzerConfig {
+    pub waveform: Option<String>,
+    pub frequency: Option<f32>,
+    pub volume: Option<f32>,
+    pub attack: Option<f32>,
+    pub release: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaletteConfig {
+    pub background: String,
+    pub planes: Vec<String>,
+}
+
+/// The directory holding the emulator's config files: `$XDG_CONFIG_HOME/chip8`, falling back
+/// to `~/.config/chip8`.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(dir.join("chip8"))
+}
+
+/// The config file location, `config.toml` in [`config_dir`].
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let data = fs::read_to_string(path)?;
+        Config::parse(&data)
+    }
+
+    /// Loads the config file from its default location, if there is one.
+    pub fn load_default() -> Result<Config> {
+        match default_path() {
+            Some(path) if path.exists() => Config::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn parse(data: &str) -> Result<Config> {
+        toml::from_str(data).map_err(|err| Error::InvalidConfig(err.to_string()))
+    }
+
+    /// Built-in palettes followed by the ones defined in the file. A palette in the file
+    /// replaces a built-in one with the same name.
+    pub fn palettes(&self) -> Result<Vec<Palette>> {
+        let mut palettes = Palette::builtin();
+        for (name, p) in self.palettes.iter() {
+            let color = |s: &String| {
+                palette::parse_color(s).ok_or_else(|| {
+                    Error::InvalidConfig(format!("palette {}: bad colour {}", name, s))
+                })
+            };
+
+            let custom = Palette::new(
+                name,
+                color(&p.background)?,
+                p.planes.iter().map(color).collect::<Result<Vec<_>>>()?,
+            );
+
+            match palettes.iter_mut().find(|existing| existing.name == *name) {
+                Some(existing) => *existing = custom,
+                None => palettes.push(custom),
+            }
+        }
+
+        Ok(palettes)
+    }
+
+    /// Built-in keymaps followed by the ones defined in the file. A keymap in the file replaces
+    /// a built-in one with the same name.
+    pub fn keymaps(&self) -> Result<Vec<Keymap>> {
+        let mut keymaps = Keymap::presets();
+        for (name, k) in self.keymaps.iter() {
+            let mut custom = match k.base.as_ref() {
+                Some(base) => keymaps
+                    .iter()
+                    .find(|existing| existing.name == *base)
+                    .cloned()
+                    .ok_or_else(|| {
+                        Error::InvalidConfig(format!("keymap {}: unknown base {}", name, base))
+                    })?,
+                None => Keymap::new(name),
+            };
+            custom.name = name.clone();
+
+            for (digit, hosts) in k.keys.iter() {
+                let key = parse_key(digit).ok_or_else(|| {
+                    Error::InvalidConfig(format!("keymap {}: bad CHIP-8 key {}", name, digit))
+                })?;
+                custom.bind(key, hosts);
+            }
+
+            match keymaps.iter_mut().find(|existing| existing.name == *name) {
+                Some(existing) => *existing = custom,
+                None => keymaps.push(custom),
+            }
+        }
+
+        Ok(keymaps)
+    }
+
+    pub fn autofire(&self) -> Result<Autofire> {
+        let mut autofire = Autofire::default();
+        if let Some(rate) = self.autofire.rate {
+            if !(rate > 0.0 && rate <= 30.0) {
+                return Err(Error::InvalidConfig(format!(
+                    "autofire: rate {} is not between 0 and 30",
+                    rate
+                )));
+            }
+            autofire.rate = rate;
+        }
+        for (host, digit) in self.autofire.keys.iter() {
+            let key = parse_key(digit).ok_or_else(|| {
+                Error::InvalidConfig(format!("autofire: bad CHIP-8 key {}", digit))
+            })?;
+            autofire.keys.insert(host.to_lowercase(), key);
+        }
+
+        Ok(autofire)
+    }
+
+    pub fn macros(&self) -> Result<Vec<Macro>> {
+        self.macros
+            .iter()
+            .map(|(name, m)| {
+                Macro::parse(name, &m.key, &m.sequence).ok_or_else(|| {
+                    Error::InvalidConfig(format!("macro {}: cannot read \"{}\"", name, m.sequence))
+                })
+            })
+            .collect()
+    }
+
+    /// The buzzer with the settings from the file applied to the defaults.
+    pub fn buzzer(&self) -> Result<Buzzer> {
+        let settings = &self.buzzer;
+        let mut buzzer = Buzzer::default();
+        if let Some(name) = settings.waveform.as_ref() {
+            buzzer.waveform = Waveform::from_name(name).ok_or_else(|| {
+                Error::InvalidConfig(format!("buzzer: unknown waveform {}", name))
+            })?;
+        }
+        if let Some(frequency) = settings.frequency {
+            buzzer.frequency = frequency;
+        }
+        if let Some(volume) = settings.volume {
+            buzzer.volume = volume;
+        }
+        if let Some(attack) = settings.attack {
+            buzzer.attack = attack / 1000.0;
+        }
+        if let Some(release) = settings.release {
+            buzzer.release = release / 1000.0;
+        }
+
+        buzzer
+            .validate()
+            .map_err(|err| Error::InvalidConfig(format!("buzzer: {}", err)))?;
+        Ok(buzzer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::Input;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            r##"
+            platform = "schip1.1"
+            anti-flicker = "or:2"
+            palette = "paper"
+            scaling = "fractional"
+            fullscreen = true
+            keypad = true
+            effects = "scanlines,bloom"
+            speed = 10
+            keymap = "azerty"
+
+            [keymaps.arrows]
+            base = "qwerty"
+            5 = ["w", "up"]
+            8 = ["s", "down"]
+
+            [keymaps.numpad]
+            0 = ["numpad0"]
+
+            [rom-keymaps]
+            INVADERS = "arrows"
+
+            [autofire]
+            rate = 12
+            Space = "5"
+
+            [macros.shuffle]
+            key = "m"
+            sequence = "4 4 wait:10 6"
+
+            [buzzer]
+            waveform = "sine"
+            frequency = 330
+            attack = 10
+
+            [palettes.paper]
+            background = "#F0EAD6"
+            planes = ["#202020"]
+
+            [palettes.green]
+            background = "#001000"
+            planes = ["#00C000"]
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(config.platform.as_deref(), Some("schip1.1"));
+        assert_eq!(config.anti_flicker.as_deref(), Some("or:2"));
+        assert_eq!(config.palette.as_deref(), Some("paper"));
+        assert_eq!(config.scaling.as_deref(), Some("fractional"));
+        assert_eq!(config.fullscreen, Some(true));
+        assert_eq!(config.keypad, Some(true));
+        assert_eq!(config.effects.as_deref(), Some("scanlines,bloom"));
+
+        assert_eq!(config.speed, Some(10));
+        assert_eq!(config.keymap.as_deref(), Some("azerty"));
+        assert_eq!(config.rom_keymaps["INVADERS"], "arrows");
+        let keymaps = config.keymaps().unwrap();
+        assert_eq!(keymaps.len(), Keymap::presets().len() + 1);
+        let arrows = keymaps.last().unwrap();
+        assert_eq!(arrows.name, "arrows");
+        assert_eq!(arrows.input("up"), Some(Input::Key5));
+        assert_eq!(arrows.input("w"), Some(Input::Key5));
+        assert_eq!(arrows.input("q"), Some(Input::Key4));
+        let numpad = keymaps.iter().find(|k| k.name == "numpad").unwrap();
+        assert_eq!(numpad.input("numpad0"), Some(Input::Key0));
+        assert_eq!(numpad.input("numpad1"), None);
+
+        let autofire = config.autofire().unwrap();
+        assert_eq!(autofire.rate, 12.0);
+        assert_eq!(autofire.keys["space"], Input::Key5);
+        let macros = config.macros().unwrap();
+        assert_eq!(
+            (macros[0].name.as_str(), macros[0].host.as_str()),
+            ("shuffle", "m")
+        );
+        assert_eq!(macros[0].frames(), 16);
+
+        let buzzer = config.buzzer().unwrap();
+        assert_eq!(buzzer.waveform, Waveform::Sine);
+        assert_eq!(buzzer.frequency, 330.0);
+        assert_eq!(buzzer.volume, Buzzer::default().volume);
+        assert_eq!(buzzer.attack, 0.01);
+
+        let palettes = config.palettes().unwrap();
+        assert_eq!(palettes.len(), Palette::builtin().len() + 1);
+        assert_eq!(palettes[0].background, [0x00, 0x10, 0x00]);
+        assert_eq!(palettes.last().unwrap().planes, vec![[0x20, 0x20, 0x20]]);
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        assert!(Config::parse("palete = \"green\"").is_err());
+
+        let config = Config::parse(
+            r##"
+            [palettes.broken]
+            background = "black"
+            planes = []
+            "##,
+        )
+        .unwrap();
+        assert!(config.palettes().is_err());
+
+        let config = Config::parse("[keymaps.broken]\ng = [\"q\"]").unwrap();
+        assert!(config.keymaps().is_err());
+        let config = Config::parse("[keymaps.broken]\nbase = \"colemak\"").unwrap();
+        assert!(config.keymaps().is_err());
+
+        let config = Config::parse("[buzzer]\nwaveform = \"saw\"").unwrap();
+        assert!(config.buzzer().is_err());
+        let config = Config::parse("[buzzer]\nvolume = 2.0").unwrap();
+        assert!(config.buzzer().is_err());
+
+        let config = Config::parse("[autofire]\nrate = 0").unwrap();
+        assert!(config.autofire().is_err());
+        let config = Config::parse("[autofire]\nspace = \"10\"").unwrap();
+        assert!(config.autofire().is_err());
+        let config = Config::parse("[macros.m]\nkey = \"m\"\nsequence = \"+5\"").unwrap();
+        assert!(config.macros().is_err());
+    }
+}
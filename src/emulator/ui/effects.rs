@@ -0,0 +1,233 @@
+use image::{Rgba, RgbaImage};
+
+const SCANLINE_BRIGHTNESS: f32 = 0.55;
+const GRID_BRIGHTNESS: f32 = 0.75;
+const BLOOM_STRENGTH: f32 = 0.6;
+const CURVATURE: f32 = 0.08;
+
+/// CRT-style filters applied to a rasterized picture. They work on the RGBA image, so the GUI,
+/// screenshots and GIFs all get the same look. Most of them need a few image pixels per emulated
+/// pixel to show.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Effects {
+    /// Darkens the bottom of every row of pixels.
+    pub scanlines: bool,
+    /// Darkens the edges of every pixel, like the mask of an LCD.
+    pub grid: bool,
+    /// Lets bright pixels glow into their neighbours.
+    pub bloom: bool,
+    /// Bends the picture like the glass of a CRT.
+    pub curvature: bool,
+}
+
+impl Effects {
+    /// Parses a comma separated list such as `scanlines,bloom`. `none` turns all effects off.
+    pub fn from_names(names: &str) -> Option<Effects> {
+        let mut effects = Effects::default();
+        for name in names.split(',').map(str::trim) {
+            match name {
+                "none" => {}
+                "scanlines" => effects.scanlines = true,
+                "grid" => effects.grid = true,
+                "bloom" => effects.bloom = true,
+                "curvature" => effects.curvature = true,
+                _ => return None,
+            }
+        }
+
+        Some(effects)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Effects::default()
+    }
+
+    /// Applies the enabled effects to an image drawn with `scale` image pixels per emulated
+    /// pixel.
+    pub fn apply(&self, image: &mut RgbaImage, scale: u32) {
+        if self.scanlines && scale >= 2 {
+            let lines = (scale / 3).max(1);
+            darken(image, SCANLINE_BRIGHTNESS, |_, y| {
+                y % scale >= scale - lines
+            });
+        }
+        if self.grid && scale >= 3 {
+            darken(image, GRID_BRIGHTNESS, |x, y| {
+                x % scale == scale - 1 || y % scale == scale - 1
+            });
+        }
+        if self.bloom {
+            bloom(image, (scale / 2).max(1));
+        }
+        if self.curvature {
+            *image = curve(image);
+        }
+    }
+}
+
+fn darken<F>(image: &mut RgbaImage, brightness: f32, f: F)
+where
+    F: Fn(u32, u32) -> bool,
+{
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if f(x, y) {
+            for c in pixel.0.iter_mut().take(3) {
+                *c = (*c as f32 * brightness) as u8;
+            }
+        }
+    }
+}
+
+/// Adds a blurred copy of the image on top of itself.
+fn bloom(image: &mut RgbaImage, radius: u32) {
+    let (width, height) = image.dimensions();
+    let mut glow: Vec<[f32; 3]> = image
+        .pixels()
+        .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32])
+        .collect();
+
+    let (width, height, radius) = (width as usize, height as usize, radius as usize);
+    blur(&mut glow, (width, 1), (height, width), radius);
+    blur(&mut glow, (height, width), (width, 1), radius);
+
+    for (pixel, glow) in image.pixels_mut().zip(glow.iter()) {
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as f32 + glow[c] * BLOOM_STRENGTH).min(255.0) as u8;
+        }
+    }
+}
+
+/// Box blur along one axis. `pixels` and `lines` are each a count and the distance between
+/// consecutive ones in `data`. Near the ends of a line the window is cut short and averaged over the
+/// pixels it still covers, so the edges keep their brightness.
+fn blur(data: &mut [[f32; 3]], pixels: (usize, usize), lines: (usize, usize), radius: usize) {
+    let (len, stride) = pixels;
+    let (lines, step) = lines;
+    let mut line = vec![[0.0f32; 3]; len];
+
+    for l in 0..lines {
+        let start = l * step;
+        for (i, p) in line.iter_mut().enumerate() {
+            *p = data[start + i * stride];
+        }
+
+        // Running sum of line[i - radius..=i + radius], clamped to the line.
+        let mut sum = [0.0f32; 3];
+        for p in &line[..radius.min(len)] {
+            for c in 0..3 {
+                sum[c] += p[c];
+            }
+        }
+
+        for i in 0..len {
+            if let Some(p) = line.get(i + radius) {
+                for c in 0..3 {
+                    sum[c] += p[c];
+                }
+            }
+            if i > radius {
+                let p = line[i - radius - 1];
+                for c in 0..3 {
+                    sum[c] -= p[c];
+                }
+            }
+
+            let count = ((i + radius + 1).min(len) - i.saturating_sub(radius)) as f32;
+            data[start + i * stride] = [sum[0] / count, sum[1] / count, sum[2] / count];
+        }
+    }
+}
+
+/// Barrel distortion. Whatever falls outside of the picture is black.
+fn curve(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (w, h) = (width as f32, height as f32);
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let u = (x as f32 + 0.5) / w * 2.0 - 1.0;
+        let v = (y as f32 + 0.5) / h * 2.0 - 1.0;
+        let k = 1.0 + CURVATURE * (u * u + v * v);
+        let sx = ((u * k + 1.0) / 2.0 * w).floor();
+        let sy = ((v * k + 1.0) / 2.0 * h).floor();
+
+        if sx < 0.0 || sy < 0.0 || sx >= w || sy >= h {
+            Rgba([0, 0, 0, 0xFF])
+        } else {
+            *image.get_pixel(sx as u32, sy as u32)
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WHITE: Rgba<u8> = Rgba([0xFF, 0xFF, 0xFF, 0xFF]);
+    const BLACK: Rgba<u8> = Rgba([0x00, 0x00, 0x00, 0xFF]);
+
+    #[test]
+    fn test_from_names() {
+        assert_eq!(Effects::from_names("none"), Some(Effects::default()));
+        let effects = Effects::from_names("scanlines, bloom").unwrap();
+        assert!(effects.scanlines && effects.bloom && !effects.grid && !effects.curvature);
+        assert_eq!(Effects::from_names("scanlines,blur"), None);
+    }
+
+    #[test]
+    fn test_blur_edges() {
+        let mut data = vec![[100.0f32, 50.0, 10.0]; 5 * 3];
+        blur(&mut data, (5, 1), (3, 5), 2);
+        blur(&mut data, (3, 5), (5, 1), 2);
+
+        for p in &data {
+            for (c, v) in p.iter().zip(&[100.0f32, 50.0, 10.0]) {
+                assert!((c - v).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let mut img = RgbaImage::from_pixel(6, 6, WHITE);
+        Effects::from_names("scanlines").unwrap().apply(&mut img, 3);
+
+        assert_eq!(img.get_pixel(0, 0), &WHITE);
+        assert_eq!(img.get_pixel(0, 1), &WHITE);
+        assert_eq!(img.get_pixel(0, 2), &Rgba([140, 140, 140, 0xFF]));
+        assert_eq!(img.get_pixel(5, 5), &Rgba([140, 140, 140, 0xFF]));
+    }
+
+    #[test]
+    fn test_grid() {
+        let mut img = RgbaImage::from_pixel(6, 6, WHITE);
+        Effects::from_names("grid").unwrap().apply(&mut img, 3);
+
+        assert_eq!(img.get_pixel(1, 1), &WHITE);
+        assert_eq!(img.get_pixel(2, 1), &Rgba([191, 191, 191, 0xFF]));
+        assert_eq!(img.get_pixel(1, 2), &Rgba([191, 191, 191, 0xFF]));
+    }
+
+    #[test]
+    fn test_bloom() {
+        let mut img = RgbaImage::from_pixel(9, 9, BLACK);
+        img.put_pixel(4, 4, WHITE);
+        Effects::from_names("bloom").unwrap().apply(&mut img, 2);
+
+        assert_eq!(img.get_pixel(4, 4), &WHITE);
+        assert!(img.get_pixel(5, 5)[0] > 0);
+        assert_eq!(img.get_pixel(7, 4), &BLACK);
+    }
+
+    #[test]
+    fn test_curvature() {
+        let img = RgbaImage::from_pixel(40, 20, WHITE);
+        let mut curved = img.clone();
+        Effects::from_names("curvature")
+            .unwrap()
+            .apply(&mut curved, 1);
+
+        assert_eq!(curved.get_pixel(20, 10), &WHITE);
+        assert_eq!(curved.get_pixel(0, 0), &BLACK);
+        assert_eq!(curved.get_pixel(39, 19), &BLACK);
+    }
+}
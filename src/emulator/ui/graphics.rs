@@ -0,0 +1,248 @@
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const SIXEL_MAX_COLORS: usize = 256;
+const KITTY_CHUNK_SIZE: usize = 4096;
+const KITTY_IMAGE_ID: u32 = 1;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Inline image protocols understood by some terminals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// DEC Sixel, supported by xterm, mlterm, foot, WezTerm and others.
+    Sixel,
+    /// The Kitty graphics protocol, also supported by WezTerm and Konsole.
+    Kitty,
+}
+
+impl Protocol {
+    pub fn from_name(name: &str) -> Option<Protocol> {
+        match name {
+            "sixel" => Some(Protocol::Sixel),
+            "kitty" => Some(Protocol::Kitty),
+            _ => None,
+        }
+    }
+
+    /// The escape sequence that shows the image at the cursor.
+    pub fn encode(self, image: &RgbaImage) -> String {
+        match self {
+            Protocol::Sixel => sixel(image),
+            Protocol::Kitty => kitty(image),
+        }
+    }
+}
+
+/// Encodes the image as Sixel. Images with more than 256 colours are reduced to a 6x6x6 colour
+/// cube first.
+pub fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let (palette, indices) = index_colors(image);
+
+    let mut out = String::new();
+    let _ = write!(out, "\x1bPq\"1;1;{};{}", width, height);
+    for (i, [r, g, b]) in palette.iter().enumerate() {
+        let percent = |c: u8| c as u32 * 100 / 255;
+        let _ = write!(
+            out,
+            "#{};2;{};{};{}",
+            i,
+            percent(*r),
+            percent(*g),
+            percent(*b)
+        );
+    }
+
+    for top in (0..height).step_by(6) {
+        let rows = (height - top).min(6);
+        let mut used: Vec<usize> = (0..rows)
+            .flat_map(|dy| &indices[(top + dy) * width..(top + dy + 1) * width])
+            .map(|i| *i as usize)
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+
+        for (n, color) in used.iter().enumerate() {
+            if n > 0 {
+                out.push('$');
+            }
+            let _ = write!(out, "#{}", color);
+
+            let sixels = (0..width).map(|x| {
+                let bits = (0..rows)
+                    .filter(|dy| indices[(top + dy) * width + x] as usize == *color)
+                    .fold(0u8, |bits, dy| bits | 1 << dy);
+                (0x3F + bits) as char
+            });
+            run_length(&mut out, sixels);
+        }
+        out.push('-');
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Encodes the image as Kitty graphics commands carrying raw RGBA data. The image always uses
+/// the same id, so drawing a new frame replaces the previous one.
+pub fn kitty(image: &RgbaImage) -> String {
+    let data = base64(image);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+
+    let mut out = String::new();
+    for (n, chunk) in chunks.iter().enumerate() {
+        let more = if n + 1 < chunks.len() { 1 } else { 0 };
+        if n == 0 {
+            let _ = write!(
+                out,
+                "\x1b_Ga=T,f=32,s={},v={},i={},q=2,m={};",
+                image.width(),
+                image.height(),
+                KITTY_IMAGE_ID,
+                more
+            );
+        } else {
+            let _ = write!(out, "\x1b_Gm={};", more);
+        }
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\x1b\\");
+    }
+
+    out
+}
+
+/// Gives every colour in the image a palette index.
+fn index_colors(image: &RgbaImage) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(image.len() / 4);
+
+    for p in image.pixels() {
+        let rgb = [p[0], p[1], p[2]];
+        if !colors.contains_key(&rgb) && colors.len() == SIXEL_MAX_COLORS {
+            return index_cube(image);
+        }
+
+        let next = colors.len();
+        let i = *colors.entry(rgb).or_insert_with(|| {
+            palette.push(rgb);
+            next as u8
+        });
+        indices.push(i);
+    }
+
+    (palette, indices)
+}
+
+fn index_cube(image: &RgbaImage) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let level = |c: u8| (c as u32 * 5 + 127) / 255;
+    let palette = (0..216u32)
+        .map(|i| {
+            let c = |l: u32| (l * 255 / 5) as u8;
+            [c(i / 36), c(i / 6 % 6), c(i % 6)]
+        })
+        .collect();
+    let indices = image
+        .pixels()
+        .map(|p| (level(p[0]) * 36 + level(p[1]) * 6 + level(p[2])) as u8)
+        .collect();
+
+    (palette, indices)
+}
+
+/// Writes the characters, using Sixel's `!<count><char>` for repeats.
+fn run_length<I: Iterator<Item = char>>(out: &mut String, chars: I) {
+    let flush = |out: &mut String, c: char, n: usize| {
+        if n > 3 {
+            let _ = write!(out, "!{}{}", n, c);
+        } else {
+            (0..n).for_each(|_| out.push(c));
+        }
+    };
+
+    let mut current = None;
+    for c in chars {
+        current = match current {
+            Some((prev, n)) if prev == c => Some((prev, n + 1)),
+            Some((prev, n)) => {
+                flush(out, prev, n);
+                Some((c, 1))
+            }
+            None => Some((c, 1)),
+        };
+    }
+    if let Some((c, n)) = current {
+        flush(out, c, n);
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b""), "");
+    }
+
+    #[test]
+    fn test_sixel() {
+        let mut image = RgbaImage::from_pixel(5, 2, Rgba([0, 0, 0, 0xFF]));
+        image.put_pixel(0, 1, Rgba([0xFF, 0xFF, 0xFF, 0xFF]));
+
+        assert_eq!(
+            sixel(&image),
+            "\x1bPq\"1;1;5;2#0;2;0;0;0#1;2;100;100;100#0@!4B$#1A!4?-\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_sixel_many_colors() {
+        let image = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8 * 8, y as u8 * 8, 0, 0xFF]));
+        let out = sixel(&image);
+        assert!(out.starts_with("\x1bPq\"1;1;32;32#0;2;0;0;0"));
+        assert!(out.contains("#215;2;100;100;100"));
+    }
+
+    #[test]
+    fn test_kitty() {
+        let image = RgbaImage::from_pixel(1, 1, Rgba([1, 2, 3, 4]));
+        assert_eq!(
+            kitty(&image),
+            "\x1b_Ga=T,f=32,s=1,v=1,i=1,q=2,m=0;AQIDBA==\x1b\\"
+        );
+
+        let image = RgbaImage::from_pixel(40, 40, Rgba([1, 2, 3, 4]));
+        let out = kitty(&image);
+        assert_eq!(out.matches("\x1b_G").count(), 3);
+        assert!(out.contains("m=1;"));
+        assert!(out.contains("\x1b_Gm=0;"));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::emulator::implementation::font_sprite;
+use crate::emulator::ui::keymap::KEYPAD;
+use crate::emulator::ui::palette::{Palette, Rgb};
+use crate::emulator::{Input, Keypad};
+use piston_window::math::Matrix2d;
+use piston_window::{rectangle, Graphics};
+
+const MAX_WIDTH: f64 = 0.4; // share of the window the keypad may take
+const GAP: f64 = 0.1; // space between keys, as a share of a key
+const GLYPH_SCALE: f64 = 1.0 / 8.0; // size of a label pixel, as a share of a key
+
+/// The on-screen keypad, a square on the right of the window with the keys in the COSMAC VIP
+/// layout. Keys light up while they are down and glow faintly while the program polls them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Panel {
+    pub x: f64,
+    pub y: f64,
+    pub size: f64,
+}
+
+impl Panel {
+    /// Makes room for the keypad in a window of `window` pixels. Returns the panel and the size
+    /// of the space left for the picture.
+    pub fn place(window: [f64; 2]) -> (Panel, [f64; 2]) {
+        let size = window[1].min(window[0] * MAX_WIDTH).floor();
+        let panel = Panel {
+            x: window[0] - size,
+            y: ((window[1] - size) / 2.0).floor(),
+            size,
+        };
+        (panel, [window[0] - size, window[1]])
+    }
+
+    /// The keys with the rectangles they are drawn in, as `[x, y, width, height]`.
+    pub fn keys(&self) -> impl Iterator<Item = (Input, [f64; 4])> + '_ {
+        let cell = self.size / 4.0;
+        let gap = cell * GAP;
+        KEYPAD.iter().enumerate().map(move |(i, key)| {
+            let (column, row) = ((i % 4) as f64, (i / 4) as f64);
+            let rect = [
+                self.x + column * cell + gap / 2.0,
+                self.y + row * cell + gap / 2.0,
+                cell - gap,
+                cell - gap,
+            ];
+            (*key, rect)
+        })
+    }
+
+    /// The key under a point of the window, if any.
+    pub fn key_at(&self, pos: [f64; 2]) -> Option<Input> {
+        self.keys()
+            .find(|(_, [x, y, w, h])| {
+                pos[0] >= *x && pos[0] < x + w && pos[1] >= *y && pos[1] < y + h
+            })
+            .map(|(key, _)| key)
+    }
+
+    pub fn draw<G: Graphics>(
+        &self,
+        keypad: &Keypad,
+        palette: &Palette,
+        transform: Matrix2d,
+        g: &mut G,
+    ) {
+        let foreground = palette.planes.first().copied().unwrap_or([0xFF; 3]);
+        for (key, rect) in self.keys() {
+            let digit = key as u8;
+            let (fill, label) = if keypad.is_pressed(digit) {
+                (rgba(foreground), rgba(palette.background))
+            } else if keypad.polled(digit) {
+                (mix(palette.background, foreground, 0.4), rgba(foreground))
+            } else {
+                (mix(palette.background, foreground, 0.15), rgba(foreground))
+            };
+            rectangle(fill, rect, transform, g);
+
+            // the label is the digit from the CHIP-8 font, centred on the key
+            let pixel = (rect[2] * GLYPH_SCALE).floor().max(1.0);
+            let left = rect[0] + ((rect[2] - 4.0 * pixel) / 2.0).floor();
+            let top = rect[1] + ((rect[3] - 5.0 * pixel) / 2.0).floor();
+            for (row, bits) in font_sprite(digit).iter().enumerate() {
+                for column in (0..4).filter(|c| bits & (0x80 >> c) != 0) {
+                    let square = [
+                        left + column as f64 * pixel,
+                        top + row as f64 * pixel,
+                        pixel,
+                        pixel,
+                    ];
+                    rectangle(label, square, transform, g);
+                }
+            }
+        }
+    }
+}
+
+fn rgba(rgb: Rgb) -> [f32; 4] {
+    [
+        rgb[0] as f32 / 255.0,
+        rgb[1] as f32 / 255.0,
+        rgb[2] as f32 / 255.0,
+        1.0,
+    ]
+}
+
+fn mix(a: Rgb, b: Rgb, t: f32) -> [f32; 4] {
+    let channel = |i: usize| (a[i] as f32 * (1.0 - t) + b[i] as f32 * t) / 255.0;
+    [channel(0), channel(1), channel(2), 1.0]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_place() {
+        let (panel, rest) = Panel::place([1000.0, 300.0]);
+        assert_eq!((panel.x, panel.y, panel.size), (700.0, 0.0, 300.0));
+        assert_eq!(rest, [700.0, 300.0]);
+
+        // narrow windows keep most of their width for the picture
+        let (panel, rest) = Panel::place([500.0, 400.0]);
+        assert_eq!((panel.x, panel.y, panel.size), (300.0, 100.0, 200.0));
+        assert_eq!(rest, [300.0, 400.0]);
+    }
+
+    #[test]
+    fn test_key_at() {
+        let panel = Panel {
+            x: 100.0,
+            y: 0.0,
+            size: 400.0,
+        };
+        assert_eq!(panel.key_at([150.0, 50.0]), Some(Input::Key1));
+        assert_eq!(panel.key_at([450.0, 50.0]), Some(Input::KeyC));
+        assert_eq!(panel.key_at([250.0, 350.0]), Some(Input::Key0));
+        assert_eq!(panel.key_at([450.0, 399.0]), None); // in the gap
+        assert_eq!(panel.key_at([50.0, 50.0]), None);
+    }
+}
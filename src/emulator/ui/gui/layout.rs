@@ -0,0 +1,81 @@
+/// How the picture is stretched to fill the window.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Scaling {
+    /// Whole multiples of the emulated resolution, so every pixel is the same size.
+    #[default]
+    Integer,
+    /// As large as fits, at the cost of uneven pixel sizes.
+    Fractional,
+}
+
+impl Scaling {
+    pub fn from_name(name: &str) -> Option<Scaling> {
+        match name {
+            "integer" => Some(Scaling::Integer),
+            "fractional" => Some(Scaling::Fractional),
+            _ => None,
+        }
+    }
+}
+
+/// Where the picture goes in the window. Whatever is left around it is letterboxed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    /// Size of one emulated pixel in window pixels.
+    pub scale: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Viewport {
+    /// Fits a picture of `picture` emulated pixels into a window of `window` pixels, keeping the
+    /// aspect ratio and centring it.
+    pub fn fit(window: [f64; 2], picture: (usize, usize), scaling: Scaling) -> Viewport {
+        let (width, height) = (picture.0.max(1) as f64, picture.1.max(1) as f64);
+        let mut scale = (window[0] / width).min(window[1] / height);
+        if scaling == Scaling::Integer {
+            scale = scale.floor().max(1.0);
+        }
+
+        let (width, height) = (width * scale, height * scale);
+        Viewport {
+            x: ((window[0] - width) / 2.0).max(0.0).floor(),
+            y: ((window[1] - height) / 2.0).max(0.0).floor(),
+            scale,
+            width,
+            height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fit_integer() {
+        let v = Viewport::fit([800.0, 600.0], (64, 32), Scaling::Integer);
+        assert_eq!(v.scale, 12.0);
+        assert_eq!((v.x, v.y, v.width, v.height), (16.0, 108.0, 768.0, 384.0));
+
+        let v = Viewport::fit([800.0, 600.0], (128, 64), Scaling::Integer);
+        assert_eq!(v.scale, 6.0);
+        assert_eq!((v.x, v.y), (16.0, 108.0));
+
+        let v = Viewport::fit([50.0, 20.0], (64, 32), Scaling::Integer);
+        assert_eq!((v.x, v.y, v.scale), (0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_fit_fractional() {
+        let v = Viewport::fit([800.0, 600.0], (64, 32), Scaling::Fractional);
+        assert_eq!(v.scale, 12.5);
+        assert_eq!((v.x, v.y, v.width, v.height), (0.0, 100.0, 800.0, 400.0));
+
+        let v = Viewport::fit([800.0, 600.0], (256, 192), Scaling::Fractional);
+        assert_eq!(v.scale, 3.125);
+        assert_eq!((v.x, v.y), (0.0, 0.0));
+    }
+}
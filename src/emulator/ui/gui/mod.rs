@@ -1,15 +1,93 @@
+mod keypad;
+mod layout;
+
 use crate::emulator;
-use crate::emulator::common::Result;
-use crate::emulator::display;
+use crate::emulator::common::{Error, Result};
+use crate::emulator::movie::Movie;
+use crate::emulator::ui::antiflicker::Mode;
+use crate::emulator::ui::audio::{Buzzer, PlayerSink};
+use crate::emulator::ui::effects::Effects;
+use crate::emulator::ui::input::{Autofire, InputLayer, Macro};
+use crate::emulator::ui::keymap::Keymap;
+use crate::emulator::ui::palette::Palette;
+use crate::emulator::ui::recorder::{DeferredFile, Recorder};
+use crate::emulator::ui::screenshot;
+use crate::emulator::ui::session::Session;
+use ::image::RgbaImage;
+use keypad::Panel;
+use log::{error, info};
 use piston_window::*;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const CLOCK_SPEED: f64 = 60.0f64;
 
-pub fn start_loop(emu: &mut emulator::Emulator) -> Result<()> {
-    let mut window: PistonWindow = WindowSettings::new("Chip-8", [640, 320])
+const NEXT_PALETTE_KEY: Key = Key::F2;
+const SCREENSHOT_KEY: Key = Key::F12;
+const RECORD_KEY: Key = Key::F9;
+const FULLSCREEN_KEY: Key = Key::F11;
+const KEYPAD_KEY: Key = Key::F4;
+const NEXT_WAVEFORM_KEY: Key = Key::F3;
+const VOLUME_DOWN_KEY: Key = Key::F5;
+const VOLUME_UP_KEY: Key = Key::F6;
+const PITCH_DOWN_KEY: Key = Key::F7;
+const PITCH_UP_KEY: Key = Key::F8;
+
+const VOLUME_STEP: f32 = 0.05;
+const PITCH_STEP: f32 = 1.0; // semitones
+
+pub type Scaling = layout::Scaling;
+pub type Viewport = layout::Viewport;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub anti_flicker: Mode,
+    pub palette: Palette,
+    pub palettes: Vec<Palette>, // cycled through with F2
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    pub keypad: bool, // show the on-screen keypad
+    pub effects: Effects,
+    pub sound: bool, // play the buzzer through aplay, Linux only
+    pub buzzer: Buzzer,
+    pub keymap: Keymap,
+    pub autofire: Autofire,
+    pub macros: Vec<Macro>,
+    pub speed: usize, // instructions per frame
+    pub replay: Option<Movie>,
+    pub record_movie: Option<(Movie, PathBuf)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            anti_flicker: Mode::default(),
+            palette: Palette::default(),
+            palettes: Palette::builtin(),
+            scaling: Scaling::default(),
+            fullscreen: false,
+            keypad: false,
+            effects: Effects::default(),
+            sound: false,
+            buzzer: Buzzer::default(),
+            keymap: Keymap::default(),
+            autofire: Autofire::default(),
+            macros: Vec::new(),
+            speed: 1,
+            replay: None,
+            record_movie: None,
+        }
+    }
+}
+
+pub fn start_loop(emu: &mut emulator::Emulator, options: Options) -> Result<()> {
+    // with the keypad, the window gets a square on the right for it
+    let width = if options.keypad { 960 } else { 640 };
+    let mut window: PistonWindow = WindowSettings::new("Chip-8", [width, 320])
         .exit_on_esc(true)
         .vsync(true)
-        .resizable(false)
+        .resizable(true)
+        .fullscreen(options.fullscreen)
         .build()
         .unwrap();
 
@@ -18,71 +96,379 @@ pub fn start_loop(emu: &mut emulator::Emulator) -> Result<()> {
     window.set_swap_buffers(true);
     window.set_lazy(false);
 
-    let mut pixels = display::Pixels::default();
+    let mut session = Session::new(options.anti_flicker);
+    if options.sound {
+        session.set_audio(Box::new(PlayerSink::new()));
+    }
+    if let Some(movie) = options.replay.clone() {
+        session.play_movie(movie);
+    }
+    if let Some((movie, path)) = options.record_movie.clone() {
+        session.record_movie(movie, path);
+    }
+    let mut buzzer = options.buzzer;
+    session.set_buzzer(buzzer);
+    let mut palette = options.palette;
+    let mut recorder: Option<Recorder<DeferredFile>> = None;
+    let mut fullscreen = options.fullscreen;
+    let mut show_keypad = options.keypad;
+    let mut cursor = [0.0, 0.0];
+    let mut clicked = None; // key held down with the mouse or a finger
+    let scaling = options.scaling;
+    let speed = options.speed;
+    let effects = options.effects;
+    let mut canvas = Canvas::new(&mut window);
+    let mut uploaded = None; // palette and detail of the picture in the texture, while it is current
+    let mut layer = InputLayer::new(
+        options.keymap.clone(),
+        options.autofire.clone(),
+        options.macros.clone(),
+    );
 
     while let Some(e) = window.next() {
         match e {
             Event::Loop(Loop::Update(args)) => {
-                let num_steps = (args.dt * CLOCK_SPEED).round() as usize;
-                for _i in 0..num_steps {
-                    match emu.step() {
-                        Ok(Some(emulator::Step::Exit)) => {
-                            return Ok(());
-                        }
-                        Ok(Some(emulator::Step::Draw(p))) => {
-                            pixels = p.clone();
-                        }
-                        Ok(_) => {}
-                        Err(err) => {
-                            return Err(err);
-                        }
-                    };
+                let frames = (args.dt * CLOCK_SPEED).round() as usize;
+                let mut running = true;
+                for _i in 0..frames {
+                    for (input, down) in layer.next_frame() {
+                        session.key(emu, input, down);
+                    }
+                    if !session.run_frame(emu, speed)? {
+                        running = false;
+                        break;
+                    }
+                    if session.dirty().is_some() {
+                        uploaded = None;
+                    }
+                    // the recorder times the animation by the frames it is given
+                    if let Some(r) = recorder.as_mut() {
+                        r.push(&session.picture(), &palette)?;
+                    }
+                }
+                if !running {
+                    break;
                 }
             }
             Event::Loop(Loop::Render(_)) => {
-                window.draw_2d(&e, |c, g, _| {
-                    clear([0.0, 0.0, 0.0, 0.0], g);
-                    pixels.iter().for_each(|p| {
-                        Rectangle::new([0.0, 1.0, 0.0, 1.0]).draw(
-                            [p.x as f64 * 10.0, p.y as f64 * 10.0, 10.0, 10.0],
+                let picture = session.picture();
+                let (panel, room) = layout_window(&window, show_keypad);
+                let view = Viewport::fit(room, picture.size(), scaling);
+
+                // effects need to work on the picture at (about) the size it is shown at
+                let detail = if effects.is_empty() {
+                    1.0
+                } else {
+                    view.scale.floor().max(1.0)
+                };
+                let current = Some((palette.clone(), detail));
+                if uploaded != current {
+                    canvas.upload(&picture.render(&palette, &effects, detail as u32))?;
+                    uploaded = current;
+                }
+
+                window.draw_2d(&e, |c, g, device| {
+                    canvas.context.encoder.flush(device);
+                    clear([0.0, 0.0, 0.0, 1.0], g);
+                    if let Some(texture) = canvas.texture.as_ref() {
+                        let scale = view.scale / detail;
+                        Image::new().draw(
+                            texture,
                             &c.draw_state,
-                            c.transform,
+                            c.transform.trans(view.x, view.y).scale(scale, scale),
                             g,
                         );
-                    });
+                    }
+                    if let Some(panel) = panel {
+                        panel.draw(emu.keypad(), &palette, c.transform, g);
+                    }
                 });
             }
+            Event::Input(Input::Move(Motion::MouseCursor(pos)), _) => cursor = pos,
+            Event::Input(Input::Move(Motion::Touch(args)), _) => {
+                let pos = args.position();
+                let pressed = match args.touch {
+                    Touch::Start => true,
+                    Touch::End | Touch::Cancel => false,
+                    Touch::Move => continue,
+                };
+                let (panel, _) = layout_window(&window, show_keypad);
+                let change = click(panel, &mut clicked, pos, pressed)
+                    .and_then(|(key, down)| layer.pointer(key, down));
+                if let Some((input, down)) = change {
+                    session.key(emu, input, down);
+                }
+            }
             Event::Input(Input::Button(args), _) => {
-                if let Button::Keyboard(key) = args.button {
-                    let input = match key {
-                        Key::D1 => Some(emulator::Input::Key1),
-                        Key::D2 => Some(emulator::Input::Key2),
-                        Key::D3 => Some(emulator::Input::Key3),
-                        Key::D4 => Some(emulator::Input::KeyC),
-                        Key::Q => Some(emulator::Input::Key4),
-                        Key::W => Some(emulator::Input::Key5),
-                        Key::E => Some(emulator::Input::Key6),
-                        Key::R => Some(emulator::Input::KeyD),
-                        Key::A => Some(emulator::Input::Key7),
-                        Key::S => Some(emulator::Input::Key8),
-                        Key::D => Some(emulator::Input::Key9),
-                        Key::F => Some(emulator::Input::KeyE),
-                        Key::Z => Some(emulator::Input::KeyA),
-                        Key::X => Some(emulator::Input::Key0),
-                        Key::C => Some(emulator::Input::KeyB),
-                        Key::V => Some(emulator::Input::KeyF),
-                        _ => None,
-                    };
-
-                    input.iter().for_each(|i| match args.state {
-                        ButtonState::Press => emu.key_press(*i),
-                        ButtonState::Release => emu.key_release(*i),
+                if args.button == Button::Mouse(MouseButton::Left) {
+                    let pressed = args.state == ButtonState::Press;
+                    let (panel, _) = layout_window(&window, show_keypad);
+                    let change = click(panel, &mut clicked, cursor, pressed)
+                        .and_then(|(key, down)| layer.pointer(key, down));
+                    if let Some((input, down)) = change {
+                        session.key(emu, input, down);
+                    }
+                }
+
+                if args.button == Button::Keyboard(KEYPAD_KEY) && args.state == ButtonState::Press {
+                    show_keypad = !show_keypad;
+                }
+
+                if args.button == Button::Keyboard(NEXT_PALETTE_KEY)
+                    && args.state == ButtonState::Press
+                {
+                    palette = next_palette(&options.palettes, &palette);
+                    info!("Palette: {}", palette.name);
+                }
+
+                if let (Button::Keyboard(key), ButtonState::Press) = (args.button, args.state) {
+                    if let Some(changed) = adjust_buzzer(buzzer, key) {
+                        buzzer = changed;
+                        session.set_buzzer(buzzer);
+                        info!(
+                            "Buzzer: {} {:.0} Hz, volume {:.0}%",
+                            buzzer.waveform.name(),
+                            buzzer.frequency,
+                            buzzer.volume * 100.0
+                        );
+                    }
+                }
+
+                if args.button == Button::Keyboard(SCREENSHOT_KEY)
+                    && args.state == ButtonState::Press
+                {
+                    let path = capture_path("png");
+                    match screenshot::save(
+                        &session.picture(),
+                        &palette,
+                        &effects,
+                        screenshot::DEFAULT_SCALE,
+                        &path,
+                    ) {
+                        Ok(()) => info!("Saved screenshot to {}", path.display()),
+                        Err(err) => error!("Could not save screenshot: {}", err),
+                    }
+                }
+
+                if args.button == Button::Keyboard(RECORD_KEY) && args.state == ButtonState::Press {
+                    match recorder.take() {
+                        Some(r) => {
+                            info!("Stopped recording after {} frames", r.frames());
+                            r.finish()?;
+                        }
+                        None => {
+                            let path = capture_path("gif");
+                            info!("Recording to {}", path.display());
+                            recorder = Some(Recorder::new(
+                                DeferredFile::new(path),
+                                screenshot::DEFAULT_SCALE,
+                                effects,
+                            ));
+                        }
+                    }
+                }
+
+                if args.button == Button::Keyboard(FULLSCREEN_KEY)
+                    && args.state == ButtonState::Press
+                {
+                    fullscreen = !fullscreen;
+                    let w = window.window.ctx.window();
+                    w.set_fullscreen(if fullscreen {
+                        Some(w.get_current_monitor())
+                    } else {
+                        None
                     });
                 }
+
+                if let Button::Keyboard(key) = args.button {
+                    let pressed = args.state == ButtonState::Press;
+                    if let Some(name) = host_key_name(key) {
+                        for (input, down) in layer.host_key(name, pressed) {
+                            session.key(emu, input, down);
+                        }
+                    }
+                }
             }
             _ => {}
         }
     }
 
-    Ok(())
+    if let Some(r) = recorder.take() {
+        r.finish()?;
+    }
+    session.finish()
+}
+
+/// Where the keypad goes, if it is shown, and the space left for the picture.
+fn layout_window(window: &PistonWindow, keypad: bool) -> (Option<Panel>, [f64; 2]) {
+    let size = window.size();
+    let size = [size.width, size.height];
+    if keypad {
+        let (panel, room) = Panel::place(size);
+        (Some(panel), room)
+    } else {
+        (None, size)
+    }
+}
+
+/// Handles the mouse button or a finger going down or up at `pos`. Returns the keypad key that
+/// was pressed or let go. A key stays down until the button is let go, wherever
+/// the pointer has moved to.
+fn click(
+    panel: Option<Panel>,
+    clicked: &mut Option<emulator::Input>,
+    pos: [f64; 2],
+    pressed: bool,
+) -> Option<(emulator::Input, bool)> {
+    if pressed {
+        let key = panel?.key_at(pos)?;
+        *clicked = Some(key);
+        Some((key, true))
+    } else {
+        clicked.take().map(|key| (key, false))
+    }
+}
+
+/// The name a [`Keymap`] uses for a key.
+fn host_key_name(key: Key) -> Option<&'static str> {
+    let name = match key {
+        Key::D0 => "0",
+        Key::D1 => "1",
+        Key::D2 => "2",
+        Key::D3 => "3",
+        Key::D4 => "4",
+        Key::D5 => "5",
+        Key::D6 => "6",
+        Key::D7 => "7",
+        Key::D8 => "8",
+        Key::D9 => "9",
+        Key::A => "a",
+        Key::B => "b",
+        Key::C => "c",
+        Key::D => "d",
+        Key::E => "e",
+        Key::F => "f",
+        Key::G => "g",
+        Key::H => "h",
+        Key::I => "i",
+        Key::J => "j",
+        Key::K => "k",
+        Key::L => "l",
+        Key::M => "m",
+        Key::N => "n",
+        Key::O => "o",
+        Key::P => "p",
+        Key::Q => "q",
+        Key::R => "r",
+        Key::S => "s",
+        Key::T => "t",
+        Key::U => "u",
+        Key::V => "v",
+        Key::W => "w",
+        Key::X => "x",
+        Key::Y => "y",
+        Key::Z => "z",
+        Key::Quote => "'",
+        Key::Comma => ",",
+        Key::Period => ".",
+        Key::Semicolon => ";",
+        Key::Slash => "/",
+        Key::Minus => "-",
+        Key::Equals => "=",
+        Key::LeftBracket => "[",
+        Key::RightBracket => "]",
+        Key::Backslash => "\\",
+        Key::Backquote => "`",
+        Key::Up => "up",
+        Key::Down => "down",
+        Key::Left => "left",
+        Key::Right => "right",
+        Key::Space => "space",
+        Key::Return => "enter",
+        Key::Tab => "tab",
+        Key::Backspace => "backspace",
+        Key::NumPad0 => "numpad0",
+        Key::NumPad1 => "numpad1",
+        Key::NumPad2 => "numpad2",
+        Key::NumPad3 => "numpad3",
+        Key::NumPad4 => "numpad4",
+        Key::NumPad5 => "numpad5",
+        Key::NumPad6 => "numpad6",
+        Key::NumPad7 => "numpad7",
+        Key::NumPad8 => "numpad8",
+        Key::NumPad9 => "numpad9",
+        Key::NumPadDivide => "numpad/",
+        Key::NumPadMultiply => "numpad*",
+        Key::NumPadMinus => "numpad-",
+        Key::NumPadPlus => "numpad+",
+        Key::NumPadPeriod => "numpad.",
+        Key::NumPadEnter => "numpadenter",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The buzzer after pressing one of the sound keys, or `None` for any other key.
+fn adjust_buzzer(mut buzzer: Buzzer, key: Key) -> Option<Buzzer> {
+    if key == NEXT_WAVEFORM_KEY {
+        buzzer.waveform = buzzer.waveform.next();
+    } else if key == VOLUME_DOWN_KEY {
+        buzzer.adjust_volume(-VOLUME_STEP);
+    } else if key == VOLUME_UP_KEY {
+        buzzer.adjust_volume(VOLUME_STEP);
+    } else if key == PITCH_DOWN_KEY {
+        buzzer.adjust_pitch(-PITCH_STEP);
+    } else if key == PITCH_UP_KEY {
+        buzzer.adjust_pitch(PITCH_STEP);
+    } else {
+        return None;
+    }
+    Some(buzzer)
+}
+
+fn capture_path(extension: &str) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    PathBuf::from(format!("chip8-{}.{}", secs, extension))
+}
+
+fn next_palette(palettes: &[Palette], current: &Palette) -> Palette {
+    let i = palettes
+        .iter()
+        .position(|p| p.name == current.name)
+        .map_or(0, |i| (i + 1) % palettes.len());
+    palettes.get(i).unwrap_or(current).clone()
+}
+
+/// The picture as a texture, updated in place as long as the resolution stays the same.
+struct Canvas {
+    context: G2dTextureContext,
+    texture: Option<G2dTexture>,
+}
+
+impl Canvas {
+    fn new(window: &mut PistonWindow) -> Self {
+        Canvas {
+            context: window.create_texture_context(),
+            texture: None,
+        }
+    }
+
+    fn upload(&mut self, image: &RgbaImage) -> Result<()> {
+        let unexpected = |err| Error::Unexpected(Box::new(err));
+        match self.texture.as_mut() {
+            Some(t) if t.get_size() == image.dimensions() => {
+                t.update(&mut self.context, image).map_err(unexpected)?
+            }
+            _ => {
+                let settings = TextureSettings::new().filter(Filter::Nearest);
+                let texture =
+                    Texture::from_image(&mut self.context, image, &settings).map_err(unexpected)?;
+                self.texture = Some(texture);
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,319 @@
+use crate::emulator::implementation::{parse_key, KEYS};
+use crate::emulator::ui::keymap::{HeldKeys, Keymap};
+use crate::emulator::Input;
+use std::collections::BTreeMap;
+
+const FRAME_RATE: f64 = 60.0;
+
+// Where a key press comes from. A CHIP-8 key is down while any of them holds it.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Keyboard,
+    Pointer,
+    Autofire,
+    Macro,
+}
+const SOURCES: usize = 4;
+
+/// A CHIP-8 key going down (`true`) or up.
+pub type KeyChange = (Input, bool);
+
+/// Host keys that press a CHIP-8 key over and over while they are held.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autofire {
+    pub rate: f64, // presses per second
+    pub keys: BTreeMap<String, Input>,
+}
+
+impl Default for Autofire {
+    fn default() -> Self {
+        Autofire {
+            rate: 10.0,
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl Autofire {
+    // frames from one press to the next, at least two so the key is seen going up
+    fn period(&self) -> u64 {
+        ((FRAME_RATE / self.rate).round() as u64).max(2)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Press(Input),
+    Release(Input),
+    Wait(u64),
+}
+
+/// A sequence of CHIP-8 key presses played when a host key is pressed. The sequence is a list
+/// of steps separated by spaces: a hex digit taps that key (down for a frame, then up for a
+/// frame), `+X` and `-X` press and release key X, and `wait:N` waits N frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub host: String,
+    actions: Vec<Action>,
+}
+
+impl Macro {
+    /// Returns `None` if a step cannot be read or a key is left down at the end.
+    pub fn parse(name: &str, host: &str, sequence: &str) -> Option<Macro> {
+        let mut actions = Vec::new();
+        let mut held = [false; 16];
+        for step in sequence.split_whitespace() {
+            if let Some(frames) = step.strip_prefix("wait:") {
+                actions.push(Action::Wait(frames.parse().ok()?));
+            } else if let Some(digit) = step.strip_prefix('+') {
+                let key = parse_key(digit)?;
+                held[key as usize] = true;
+                actions.push(Action::Press(key));
+            } else if let Some(digit) = step.strip_prefix('-') {
+                let key = parse_key(digit)?;
+                held[key as usize] = false;
+                actions.push(Action::Release(key));
+            } else {
+                let key = parse_key(step)?;
+                actions.extend(&[
+                    Action::Press(key),
+                    Action::Wait(1),
+                    Action::Release(key),
+                    Action::Wait(1),
+                ]);
+            }
+        }
+        if held.iter().any(|h| *h) {
+            return None;
+        }
+
+        Some(Macro {
+            name: name.to_string(),
+            host: host.to_lowercase(),
+            actions,
+        })
+    }
+
+    /// How many frames the macro takes to play.
+    pub fn frames(&self) -> u64 {
+        self.actions
+            .iter()
+            .map(|a| match a {
+                Action::Wait(n) => *n,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+// A macro being played: which one, the next action and the frame it is due.
+#[derive(Debug)]
+struct Playing {
+    index: usize,
+    next: usize,
+    at: u64,
+}
+
+/// Turns host input into CHIP-8 key changes: keys from the keymap, keys clicked on screen,
+/// autofire and macros. Autofire and macros follow the frames, so `next_frame` has to be called
+/// before every frame the emulator runs.
+#[derive(Debug)]
+pub struct InputLayer {
+    keymap: Keymap,
+    held: HeldKeys,
+    autofire: Autofire,
+    macros: Vec<Macro>,
+    firing: BTreeMap<String, u64>, // autofire host keys held, with the frame they went down on
+    playing: Vec<Playing>,
+    sources: [[bool; SOURCES]; 16],
+    frame: u64,
+}
+
+impl InputLayer {
+    pub fn new(keymap: Keymap, autofire: Autofire, macros: Vec<Macro>) -> Self {
+        InputLayer {
+            keymap,
+            held: HeldKeys::default(),
+            autofire,
+            macros,
+            firing: BTreeMap::new(),
+            playing: Vec::new(),
+            sources: [[false; SOURCES]; 16],
+            frame: 0,
+        }
+    }
+
+    /// A host key going down or up. Autofire and macro keys take precedence over the keymap.
+    pub fn host_key(&mut self, host: &str, pressed: bool) -> Vec<KeyChange> {
+        if let Some(key) = self.autofire.keys.get(host).copied() {
+            if pressed {
+                self.firing.entry(host.to_string()).or_insert(self.frame);
+            } else {
+                self.firing.remove(host);
+            }
+            return self.update_autofire(key).into_iter().collect();
+        }
+
+        if let Some(index) = self.macros.iter().position(|m| m.host == host) {
+            if pressed && !self.playing.iter().any(|p| p.index == index) {
+                self.playing.push(Playing {
+                    index,
+                    next: 0,
+                    at: self.frame,
+                });
+            }
+            return Vec::new();
+        }
+
+        self.held
+            .update(&self.keymap, host, pressed)
+            .and_then(|(key, down)| self.set(key, Source::Keyboard, down))
+            .into_iter()
+            .collect()
+    }
+
+    /// A key on the on-screen keypad going down or up.
+    pub fn pointer(&mut self, key: Input, pressed: bool) -> Option<KeyChange> {
+        self.set(key, Source::Pointer, pressed)
+    }
+
+    /// Moves on to the next frame. Returns the changes autofire and macros make at its start.
+    pub fn next_frame(&mut self) -> Vec<KeyChange> {
+        self.frame += 1;
+        let mut changes: Vec<KeyChange> = KEYS
+            .iter()
+            .filter_map(|key| self.update_autofire(*key))
+            .collect();
+
+        let mut playing = std::mem::take(&mut self.playing);
+        for p in playing.iter_mut() {
+            let count = self.macros[p.index].actions.len();
+            while p.next < count && p.at <= self.frame {
+                let change = match self.macros[p.index].actions[p.next] {
+                    Action::Press(key) => self.set(key, Source::Macro, true),
+                    Action::Release(key) => self.set(key, Source::Macro, false),
+                    Action::Wait(frames) => {
+                        p.at = self.frame + frames;
+                        None
+                    }
+                };
+                changes.extend(change);
+                p.next += 1;
+            }
+        }
+        playing.retain(|p| p.next < self.macros[p.index].actions.len());
+        self.playing = playing;
+
+        changes
+    }
+
+    /// Whether the CHIP-8 key is down.
+    pub fn is_down(&self, key: Input) -> bool {
+        self.sources[key as usize].iter().any(|s| *s)
+    }
+
+    // Autofire keys are down for the first half of every period.
+    fn update_autofire(&mut self, key: Input) -> Option<KeyChange> {
+        let period = self.autofire.period();
+        let frame = self.frame;
+        let down = self.firing.iter().any(|(host, start)| {
+            self.autofire.keys.get(host) == Some(&key) && (frame - start) % period < period / 2
+        });
+        self.set(key, Source::Autofire, down)
+    }
+
+    fn set(&mut self, key: Input, source: Source, down: bool) -> Option<KeyChange> {
+        let was = self.is_down(key);
+        self.sources[key as usize][source as usize] = down;
+        let now = self.is_down(key);
+        if was == now {
+            None
+        } else {
+            Some((key, now))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layer() -> InputLayer {
+        let mut autofire = Autofire::default();
+        autofire.keys.insert("space".to_string(), Input::Key5);
+        let macros = vec![Macro::parse("swap", "m", "4 wait:2 +6 wait:3 -6").unwrap()];
+        InputLayer::new(Keymap::default(), autofire, macros)
+    }
+
+    // the state of a key at the start of each of the next `frames` frames
+    fn run(layer: &mut InputLayer, key: Input, frames: usize) -> Vec<bool> {
+        (0..frames)
+            .map(|_| {
+                layer.next_frame();
+                layer.is_down(key)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_macro() {
+        let m = Macro::parse("swap", "M", "4 wait:2 +6 wait:3 -6").unwrap();
+        assert_eq!(m.host, "m");
+        assert_eq!(m.frames(), 7);
+
+        assert!(Macro::parse("", "m", "").is_some());
+        assert!(Macro::parse("", "m", "g").is_none());
+        assert!(Macro::parse("", "m", "wait:x").is_none());
+        assert!(Macro::parse("", "m", "+5 4").is_none());
+    }
+
+    #[test]
+    fn test_macro() {
+        let mut layer = layer();
+        assert_eq!(layer.host_key("m", true), vec![]);
+        layer.host_key("m", false);
+
+        let mut changes = Vec::new();
+        for _ in 0..10 {
+            changes.push(layer.next_frame());
+        }
+        assert_eq!(changes[0], vec![(Input::Key4, true)]);
+        assert_eq!(changes[1], vec![(Input::Key4, false)]);
+        assert_eq!(changes[4], vec![(Input::Key6, true)]);
+        assert_eq!(changes[7], vec![(Input::Key6, false)]);
+        assert_eq!(changes.iter().flatten().count(), 4);
+    }
+
+    #[test]
+    fn test_autofire() {
+        let mut layer = layer();
+        assert_eq!(layer.host_key("space", true), vec![(Input::Key5, true)]);
+        // six frames per press at 10 per second
+        let states = run(&mut layer, Input::Key5, 8);
+        assert_eq!(
+            states,
+            vec![true, true, false, false, false, true, true, true]
+        );
+
+        assert_eq!(layer.host_key("space", false), vec![(Input::Key5, false)]);
+        assert!(run(&mut layer, Input::Key5, 8).iter().all(|down| !down));
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut layer = layer();
+        assert_eq!(layer.host_key("w", true), vec![(Input::Key5, true)]);
+        assert_eq!(layer.pointer(Input::Key5, true), None);
+        assert_eq!(layer.host_key("w", false), vec![]);
+        assert_eq!(
+            layer.pointer(Input::Key5, false),
+            Some((Input::Key5, false))
+        );
+
+        // autofire lets go of the key, but the keyboard still holds it
+        layer.host_key("space", true);
+        layer.host_key("w", true);
+        assert!(run(&mut layer, Input::Key5, 6).iter().all(|down| *down));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::emulator::implementation::KEYS;
+use crate::emulator::Input;
+use std::collections::{BTreeMap, HashSet};
+
+/// The CHIP-8 keypad row by row, laid out the way it is on the COSMAC VIP:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+pub const KEYPAD: [Input; 16] = [
+    Input::Key1,
+    Input::Key2,
+    Input::Key3,
+    Input::KeyC,
+    Input::Key4,
+    Input::Key5,
+    Input::Key6,
+    Input::KeyD,
+    Input::Key7,
+    Input::Key8,
+    Input::Key9,
+    Input::KeyE,
+    Input::KeyA,
+    Input::Key0,
+    Input::KeyB,
+    Input::KeyF,
+];
+
+const QWERTY: [&str; 16] = [
+    "1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "z", "x", "c", "v",
+];
+const AZERTY: [&str; 16] = [
+    "1", "2", "3", "4", "a", "z", "e", "r", "q", "s", "d", "f", "w", "x", "c", "v",
+];
+const DVORAK: [&str; 16] = [
+    "1", "2", "3", "4", "'", ",", ".", "p", "a", "o", "e", "u", ";", "q", "j", "k",
+];
+// the numpad digits press the same hex digit, the keys around them A to F
+const NUMPAD: [&str; 16] = [
+    "numpad0",
+    "numpad1",
+    "numpad2",
+    "numpad3",
+    "numpad4",
+    "numpad5",
+    "numpad6",
+    "numpad7",
+    "numpad8",
+    "numpad9",
+    "numpad/",
+    "numpad*",
+    "numpad-",
+    "numpad+",
+    "numpadenter",
+    "numpad.",
+];
+
+/// Which host keys press which CHIP-8 keys. Host keys are named by what is printed on them in
+/// lower case (`q`, `1`, `;`), or `up`, `down`, `left`, `right`, `space`, `enter`, `tab`,
+/// `backspace` and `numpad0` to `numpad9`, `numpad/`, `numpad*`, `numpad-`, `numpad+`,
+/// `numpad.` and `numpadenter`. Any number of host keys can press the same CHIP-8 key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    pub name: String,
+    bindings: BTreeMap<String, Input>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::preset("qwerty").unwrap()
+    }
+}
+
+impl Keymap {
+    pub fn new(name: &str) -> Self {
+        Keymap {
+            name: name.to_string(),
+            bindings: BTreeMap::new(),
+        }
+    }
+
+    /// The built-in keymaps: `qwerty` (the default), `azerty` and `dvorak` put the keypad on
+    /// the keys under 1 to 4, `numpad` uses the numeric keypad.
+    pub fn presets() -> Vec<Keymap> {
+        ["qwerty", "azerty", "dvorak", "numpad"]
+            .iter()
+            .filter_map(|name| Keymap::preset(name))
+            .collect()
+    }
+
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let mut keymap = Keymap::new(name);
+        match name {
+            "qwerty" => keymap.bind_keypad(&QWERTY),
+            "azerty" => keymap.bind_keypad(&AZERTY),
+            "dvorak" => keymap.bind_keypad(&DVORAK),
+            "numpad" => {
+                for (key, host) in KEYS.iter().zip(NUMPAD.iter()) {
+                    keymap.bindings.insert(host.to_string(), *key);
+                }
+            }
+            _ => return None,
+        }
+        Some(keymap)
+    }
+
+    fn bind_keypad(&mut self, hosts: &[&str; 16]) {
+        for (key, host) in KEYPAD.iter().zip(hosts.iter()) {
+            self.bindings.insert(host.to_string(), *key);
+        }
+    }
+
+    /// Makes `hosts` the only host keys that press `key`. A host key that pressed some other
+    /// key before is moved.
+    pub fn bind(&mut self, key: Input, hosts: &[String]) {
+        self.bindings.retain(|_, k| *k != key);
+        for host in hosts {
+            self.bindings.insert(host.to_lowercase(), key);
+        }
+    }
+
+    /// Lets `hosts` press `key` as well as the host keys already bound to it.
+    pub fn add(&mut self, key: Input, hosts: &[String]) {
+        for host in hosts {
+            self.bindings.insert(host.to_lowercase(), key);
+        }
+    }
+
+    /// The CHIP-8 key pressed by a host key.
+    pub fn input(&self, host: &str) -> Option<Input> {
+        self.bindings.get(host).copied()
+    }
+
+    /// The host keys that press `key`.
+    pub fn host_keys(&self, key: Input) -> Vec<&str> {
+        self.bindings
+            .iter()
+            .filter(|(_, k)| **k == key)
+            .map(|(host, _)| host.as_str())
+            .collect()
+    }
+}
+
+/// Host keys that are held down. With several host keys bound to one CHIP-8 key, that key is
+/// only released when the last of them goes up.
+#[derive(Debug, Default)]
+pub struct HeldKeys {
+    held: HashSet<String>,
+}
+
+impl HeldKeys {
+    /// Records a host key going down or up. Returns the CHIP-8 key it is bound to and whether
+    /// that key is now pressed.
+    pub fn update(&mut self, keymap: &Keymap, host: &str, pressed: bool) -> Option<(Input, bool)> {
+        let key = keymap.input(host)?;
+        if pressed {
+            self.held.insert(host.to_string());
+        } else {
+            self.held.remove(host);
+        }
+
+        let down = self.held.iter().any(|h| keymap.input(h) == Some(key));
+        Some((key, down))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let qwerty = Keymap::default();
+        assert_eq!(qwerty.name, "qwerty");
+        assert_eq!(qwerty.input("4"), Some(Input::KeyC));
+        assert_eq!(qwerty.input("x"), Some(Input::Key0));
+        assert_eq!(qwerty.input("y"), None);
+
+        let azerty = Keymap::preset("azerty").unwrap();
+        assert_eq!(azerty.input("a"), Some(Input::Key4));
+        assert_eq!(azerty.input("w"), Some(Input::KeyA));
+
+        let numpad = Keymap::preset("numpad").unwrap();
+        assert_eq!(numpad.input("numpad7"), Some(Input::Key7));
+        assert_eq!(numpad.input("numpad."), Some(Input::KeyF));
+
+        assert_eq!(Keymap::presets().len(), 4);
+        assert!(Keymap::presets().iter().all(|k| k.bindings.len() == 16));
+        assert_eq!(Keymap::preset("colemak"), None);
+    }
+
+    #[test]
+    fn test_bind() {
+        let mut keymap = Keymap::default();
+        keymap.bind(Input::Key5, &["w".to_string(), "Up".to_string()]);
+        assert_eq!(keymap.host_keys(Input::Key5), vec!["up", "w"]);
+
+        keymap.bind(Input::Key8, &["w".to_string()]);
+        assert_eq!(keymap.host_keys(Input::Key5), vec!["up"]);
+        assert_eq!(keymap.input("s"), None);
+        assert_eq!(keymap.input("w"), Some(Input::Key8));
+
+        keymap.add(Input::Key8, &["down".to_string()]);
+        assert_eq!(keymap.host_keys(Input::Key8), vec!["down", "w"]);
+    }
+
+    #[test]
+    fn test_held_keys() {
+        let mut keymap = Keymap::default();
+        keymap.bind(Input::Key5, &["w".to_string(), "up".to_string()]);
+        let mut held = HeldKeys::default();
+
+        assert_eq!(held.update(&keymap, "w", true), Some((Input::Key5, true)));
+        assert_eq!(held.update(&keymap, "up", true), Some((Input::Key5, true)));
+        assert_eq!(held.update(&keymap, "w", false), Some((Input::Key5, true)));
+        assert_eq!(
+            held.update(&keymap, "up", false),
+            Some((Input::Key5, false))
+        );
+        assert_eq!(held.update(&keymap, "y", true), None);
+    }
+}
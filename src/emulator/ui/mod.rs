@@ -1 +1,16 @@
+pub mod antiflicker;
+pub mod audio;
+pub mod config;
+pub mod effects;
+pub mod graphics;
 pub mod gui;
+pub mod input;
+pub mod keymap;
+pub mod palette;
+pub mod presets;
+pub mod recorder;
+pub mod render;
+pub mod screenshot;
+pub mod session;
+pub mod terminal;
+pub mod text;
@@ -0,0 +1,134 @@
+pub type Rgb = [u8; 3];
+
+/// Colours used to draw the monochrome and multi-plane displays.
+///
+/// `planes` is indexed by the plane bits of a pixel minus one, so with XO-CHIP's two planes the
+/// entries are: plane 1, plane 2, and both planes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub background: Rgb,
+    pub planes: Vec<Rgb>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::builtin().remove(0)
+    }
+}
+
+impl Palette {
+    pub fn new(name: &str, background: Rgb, planes: Vec<Rgb>) -> Self {
+        Palette {
+            name: name.to_string(),
+            background,
+            planes,
+        }
+    }
+
+    pub fn builtin() -> Vec<Palette> {
+        vec![
+            Palette::new(
+                "green",
+                [0x00, 0x00, 0x00],
+                vec![[0x00, 0xFF, 0x00], [0x00, 0x80, 0x00], [0xA0, 0xFF, 0xA0]],
+            ),
+            Palette::new(
+                "amber",
+                [0x14, 0x0C, 0x00],
+                vec![[0xFF, 0xB0, 0x00], [0x99, 0x5C, 0x00], [0xFF, 0xE0, 0x90]],
+            ),
+            Palette::new(
+                "white",
+                [0x00, 0x00, 0x00],
+                vec![[0xFF, 0xFF, 0xFF], [0x80, 0x80, 0x80], [0xC0, 0xC0, 0xC0]],
+            ),
+            Palette::new(
+                "lcd",
+                [0x9B, 0xBC, 0x0F],
+                vec![[0x0F, 0x38, 0x0F], [0x8B, 0xAC, 0x0F], [0x30, 0x62, 0x30]],
+            ),
+            Palette::new(
+                "high-contrast",
+                [0x00, 0x00, 0x00],
+                vec![[0xFF, 0xFF, 0xFF], [0xFF, 0xFF, 0x00], [0x00, 0xFF, 0xFF]],
+            ),
+            Palette::new(
+                "octo",
+                [0x99, 0x66, 0x00],
+                vec![[0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]],
+            ),
+        ]
+    }
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        Palette::builtin().into_iter().find(|p| p.name == name)
+    }
+
+    /// Colour of a pixel lit on `planes`. Combinations without a colour of their own use the
+    /// last one in the palette.
+    pub fn color(&self, planes: u8) -> Rgb {
+        if planes == 0 || self.planes.is_empty() {
+            return self.background;
+        }
+
+        let i = (planes as usize - 1).min(self.planes.len() - 1);
+        self.planes[i]
+    }
+
+    /// Colour of a pixel lit on `planes` at the given brightness, fading towards the background.
+    pub fn shade(&self, planes: u8, intensity: f32) -> Rgb {
+        let fg = self.color(planes);
+        let bg = self.background;
+        let mix = |i: usize| {
+            let v = bg[i] as f32 + (fg[i] as f32 - bg[i] as f32) * intensity.clamp(0.0, 1.0);
+            v.round() as u8
+        };
+
+        [mix(0), mix(1), mix(2)]
+    }
+}
+
+/// Parses a colour written as `#RRGGBB`.
+pub fn parse_color(s: &str) -> Option<Rgb> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#FFB000"), Some([0xFF, 0xB0, 0x00]));
+        assert_eq!(parse_color("#0f380f"), Some([0x0F, 0x38, 0x0F]));
+        assert_eq!(parse_color("FFB000"), None);
+        assert_eq!(parse_color("#FFB0"), None);
+        assert_eq!(parse_color("#GGGGGG"), None);
+    }
+
+    #[test]
+    fn test_color_and_shade() {
+        let p = Palette::new("test", [0, 0, 0], vec![[200, 100, 50], [10, 20, 30]]);
+        assert_eq!(p.color(0), [0, 0, 0]);
+        assert_eq!(p.color(1), [200, 100, 50]);
+        assert_eq!(p.color(2), [10, 20, 30]);
+        assert_eq!(p.color(3), [10, 20, 30]);
+        assert_eq!(p.shade(1, 0.5), [100, 50, 25]);
+        assert_eq!(p.shade(1, 0.0), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_builtin_names() {
+        for name in &["green", "amber", "white", "lcd", "high-contrast"] {
+            assert!(Palette::from_name(name).is_some());
+        }
+        assert_eq!(Palette::default().name, "green");
+    }
+}
@@ -0,0 +1,198 @@
+use crate::emulator::common::{Error, Result};
+use crate::emulator::implementation;
+use crate::emulator::ui::config;
+use crate::emulator::ui::keymap::Keymap;
+use crate::emulator::Quirks;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BUILTIN: &str = include_str!("presets.toml");
+
+/// Settings for one ROM. Anything left out is taken from the config file and the defaults, and
+/// command line arguments take precedence.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preset {
+    pub name: Option<String>,
+    pub platform: Option<String>,
+    pub quirks: Option<String>,
+    pub speed: Option<usize>,
+    pub palette: Option<String>,
+    pub keymap: Option<String>,
+    pub keys: BTreeMap<String, Vec<String>>,
+}
+
+impl Preset {
+    /// Replaces the settings that `other` has.
+    fn merge(&mut self, other: Preset) {
+        let Preset {
+            name,
+            platform,
+            quirks,
+            speed,
+            palette,
+            keymap,
+            keys,
+        } = other;
+        self.name = name.or(self.name.take());
+        self.platform = platform.or(self.platform.take());
+        self.quirks = quirks.or(self.quirks.take());
+        self.speed = speed.or(self.speed);
+        self.palette = palette.or(self.palette.take());
+        self.keymap = keymap.or(self.keymap.take());
+        self.keys.extend(keys);
+    }
+
+    /// `quirks` applied on top of the quirks of the platform.
+    pub fn quirks(&self, platform: Quirks) -> Result<Quirks> {
+        match self.quirks.as_ref() {
+            Some(settings) => platform.with_settings(settings).ok_or_else(|| {
+                Error::InvalidConfig(format!("preset: cannot read quirks \"{}\"", settings))
+            }),
+            None => Ok(platform),
+        }
+    }
+
+    /// Adds the preset's extra keys to the keymap.
+    pub fn add_keys(&self, keymap: &mut Keymap) -> Result<()> {
+        for (digit, hosts) in self.keys.iter() {
+            let key = implementation::parse_key(digit)
+                .ok_or_else(|| Error::InvalidConfig(format!("preset: bad CHIP-8 key {}", digit)))?;
+            keymap.add(key, hosts);
+        }
+        Ok(())
+    }
+}
+
+/// Presets for known ROMs, keyed by the SHA-1 hash of the ROM (see [`storage::rom_hash`]).
+///
+/// [`storage::rom_hash`]: crate::emulator::storage::rom_hash
+#[derive(Debug, Default)]
+pub struct Presets {
+    presets: BTreeMap<String, Preset>,
+}
+
+/// The user's presets file, `presets.toml` in the config directory.
+pub fn default_path() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("presets.toml"))
+}
+
+impl Presets {
+    /// The presets that come with the emulator.
+    pub fn builtin() -> Presets {
+        Presets::parse(BUILTIN).expect("built-in presets are valid")
+    }
+
+    /// The built-in presets, changed by the user's presets file if there is one.
+    pub fn load_default() -> Result<Presets> {
+        let mut presets = Presets::builtin();
+        if let Some(path) = default_path().filter(|path| path.exists()) {
+            presets.merge(Presets::load(&path)?);
+        }
+        Ok(presets)
+    }
+
+    pub fn load(path: &Path) -> Result<Presets> {
+        Presets::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(data: &str) -> Result<Presets> {
+        let presets: BTreeMap<String, Preset> =
+            toml::from_str(data).map_err(|err| Error::InvalidConfig(err.to_string()))?;
+        Ok(Presets {
+            presets: presets
+                .into_iter()
+                .map(|(hash, preset)| (hash.to_lowercase(), preset))
+                .collect(),
+        })
+    }
+
+    /// Adds the presets in `other`. Where both have a preset for the same ROM, the settings in
+    /// `other` win.
+    pub fn merge(&mut self, other: Presets) {
+        for (hash, preset) in other.presets {
+            self.presets.entry(hash).or_default().merge(preset);
+        }
+    }
+
+    pub fn find(&self, rom_hash: &str) -> Option<&Preset> {
+        self.presets.get(rom_hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{storage, Edge, Input, Platform};
+
+    const INVADERS: &str = "f100197f0f2f05b4f3c8c31ab9c2c3930d3e9571";
+
+    #[test]
+    fn test_builtin() {
+        let presets = Presets::builtin();
+        let invaders = presets.find(INVADERS).unwrap();
+        assert_eq!(invaders.name.as_deref(), Some("Space Invaders"));
+
+        let mut keymap = Keymap::default();
+        invaders.add_keys(&mut keymap).unwrap();
+        assert_eq!(keymap.input("left"), Some(Input::Key4));
+        assert_eq!(keymap.input("q"), Some(Input::Key4));
+
+        // every preset has to work with the ROMs that come with the emulator
+        for entry in fs::read_dir("roms").unwrap() {
+            let rom = fs::read(entry.unwrap().path()).unwrap();
+            if let Some(preset) = presets.find(&storage::rom_hash(&rom)) {
+                preset.quirks(Quirks::default()).unwrap();
+                preset.add_keys(&mut Keymap::default()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut presets = Presets::builtin();
+        presets.merge(
+            Presets::parse(&format!(
+                r#"
+                ["{}"]
+                speed = 20
+                quirks = "vertical-edge=wrap"
+
+                ["{}".keys]
+                5 = ["enter"]
+
+                [0123456789ABCDEF0123456789ABCDEF01234567]
+                name = "Homebrew"
+                "#,
+                INVADERS, INVADERS
+            ))
+            .unwrap(),
+        );
+
+        let invaders = presets.find(INVADERS).unwrap();
+        assert_eq!(invaders.name.as_deref(), Some("Space Invaders"));
+        assert_eq!(invaders.speed, Some(20));
+        assert_eq!(invaders.keys["4"], vec!["left"]);
+        assert_eq!(invaders.keys["5"], vec!["enter"]);
+
+        let quirks = invaders.quirks(Platform::Chip8.quirks()).unwrap();
+        assert_eq!(quirks.vertical_edge, Edge::Wrap);
+
+        assert!(presets
+            .find("0123456789abcdef0123456789abcdef01234567")
+            .is_some());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Presets::parse("[abc]\nsped = 3").is_err());
+
+        let presets =
+            Presets::parse("[abc]\nquirks = \"wobble=yes\"\nkeys = { x = [\"q\"] }").unwrap();
+        let preset = presets.find("abc").unwrap();
+        assert!(preset.quirks(Quirks::default()).is_err());
+        assert!(preset.add_keys(&mut Keymap::default()).is_err());
+    }
+}
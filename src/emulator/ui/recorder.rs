@@ -0,0 +1,303 @@
+use crate::emulator::common::{Error, Result};
+use crate::emulator::ui::effects::Effects;
+use crate::emulator::ui::palette::Palette;
+use crate::emulator::ui::render::Picture;
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+const FRAMES_PER_SECOND: u64 = 60;
+// NeuQuant sampling for pictures with too many colours, 1 (best) to 30 (fastest). Speed 1 takes
+// long enough per frame to hold up the GUI.
+const QUANTIZE_SPEED: i32 = 10;
+
+/// Writes every frame it is given to an animated GIF, assuming frames arrive at 60 Hz.
+///
+/// GIF delays are counted in hundredths of a second, so frames alternate between two and one
+/// hundredths to keep the animation in step with the emulator. Runs of identical frames are
+/// written once with their delays added together.
+pub struct Recorder<W: Write> {
+    writer: Option<W>,
+    encoder: Option<gif::Encoder<W>>,
+    scale: u32,
+    effects: Effects,
+    size: (u32, u32),
+    pending: Option<RgbaImage>,
+    pending_start: u64,
+    frames: u64,
+}
+
+impl<W: Write> Recorder<W> {
+    /// The size of the animation is taken from the first frame, `scale` image pixels per
+    /// emulated pixel. Later frames with a different resolution are scaled to fit.
+    pub fn new(writer: W, scale: u32, effects: Effects) -> Self {
+        Recorder {
+            writer: Some(writer),
+            encoder: None,
+            scale: scale.max(1),
+            effects,
+            size: (0, 0),
+            pending: None,
+            pending_start: 0,
+            frames: 0,
+        }
+    }
+
+    /// Number of frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn push(&mut self, picture: &Picture, palette: &Palette) -> Result<()> {
+        if self.frames == 0 {
+            let (width, height) = picture.size();
+            self.size = (width as u32 * self.scale, height as u32 * self.scale);
+        }
+
+        let image = self.fit(picture, palette);
+        if self.pending.as_ref() != Some(&image) {
+            self.flush()?;
+            self.pending = Some(image);
+            self.pending_start = self.frames;
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes out the last frame and the end of the file. Nothing at all is written if no frame
+    /// was pushed.
+    pub fn finish(mut self) -> Result<()> {
+        self.flush()?;
+        if let Some(encoder) = self.encoder.take() {
+            encoder.into_inner()?.flush()?;
+        }
+        Ok(())
+    }
+
+    fn fit(&self, picture: &Picture, palette: &Palette) -> RgbaImage {
+        let (width, height) = picture.size();
+        let (canvas_width, canvas_height) = self.size;
+        if (width as u32 * self.scale, height as u32 * self.scale) == self.size {
+            return picture.render(palette, &self.effects, self.scale);
+        }
+
+        let scale = (canvas_width / width.max(1) as u32).min(canvas_height / height.max(1) as u32);
+        let image = picture.render(palette, &self.effects, scale);
+        let left = canvas_width.saturating_sub(image.width()) / 2;
+        let top = canvas_height.saturating_sub(image.height()) / 2;
+        RgbaImage::from_fn(canvas_width, canvas_height, |x, y| {
+            if x < left || y < top || x - left >= image.width() || y - top >= image.height() {
+                Rgba([0, 0, 0, 0xFF])
+            } else {
+                *image.get_pixel(x - left, y - top)
+            }
+        })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let image = match self.pending.take() {
+            Some(image) => image,
+            None => return Ok(()),
+        };
+
+        let (width, height) = (image.width() as u16, image.height() as u16);
+        let mut frame = to_gif_frame(&image);
+
+        if self.encoder.is_none() {
+            let writer = self.writer.take().unwrap();
+            let mut encoder = gif::Encoder::new(writer, width, height, &[]).map_err(encoding)?;
+            encoder
+                .set_repeat(gif::Repeat::Infinite)
+                .map_err(encoding)?;
+            self.encoder = Some(encoder);
+        }
+        let encoder = self.encoder.as_mut().unwrap();
+
+        // a GIF delay goes up to 655.35 s, so a picture shown longer is written several times
+        let mut delay = hundredths(self.frames) - hundredths(self.pending_start);
+        loop {
+            frame.delay = delay.min(u16::MAX as u64) as u16;
+            encoder.write_frame(&frame).map_err(encoding)?;
+            delay -= frame.delay as u64;
+            if delay == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A file that is only created once something is written to it, so that a recording stopped
+/// before its first frame leaves no empty file behind.
+pub struct DeferredFile {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl DeferredFile {
+    pub fn new(path: PathBuf) -> Self {
+        DeferredFile { path, file: None }
+    }
+
+    fn file(&mut self) -> io::Result<&mut BufWriter<File>> {
+        if self.file.is_none() {
+            self.file = Some(BufWriter::new(File::create(&self.path)?));
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl Write for DeferredFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn encoding(err: gif::EncodingError) -> Error {
+    match err {
+        gif::EncodingError::Io(err) => Error::IOError(err),
+        err => Error::Unexpected(Box::new(err)),
+    }
+}
+
+/// Time at which the nth frame starts, in hundredths of a second.
+fn hundredths(frame: u64) -> u64 {
+    (frame * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND
+}
+
+/// Uses the exact colours of the image when there are few enough of them for a GIF palette,
+/// and quantizes them otherwise.
+fn to_gif_frame(image: &RgbaImage) -> gif::Frame<'static> {
+    let (width, height) = (image.width() as u16, image.height() as u16);
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(image.len() / 4);
+
+    for Rgba([r, g, b, _]) in image.pixels() {
+        let next = colors.len();
+        if next > 0xFF && !colors.contains_key(&[*r, *g, *b]) {
+            let mut rgba = image.clone().into_raw();
+            return gif::Frame::from_rgba_speed(width, height, &mut rgba, QUANTIZE_SPEED);
+        }
+
+        let index = *colors.entry([*r, *g, *b]).or_insert_with(|| {
+            palette.extend_from_slice(&[*r, *g, *b]);
+            next as u8
+        });
+        indices.push(index);
+    }
+
+    gif::Frame::from_palette_pixels(width, height, &indices, &palette, None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::ui::antiflicker::Blended;
+    use crate::emulator::Framebuffer;
+    use image::AnimationDecoder;
+
+    fn picture(lit: usize) -> Picture {
+        let mut fb = Framebuffer::new(8, 4, 1);
+        fb.set(lit, 0, 1);
+        Picture::Mono(Blended::from(&fb))
+    }
+
+    #[test]
+    fn test_hundredths() {
+        let delays: Vec<u64> = (0..6).map(|n| hundredths(n + 1) - hundredths(n)).collect();
+        assert_eq!(delays, vec![2, 1, 2, 2, 1, 2]);
+        assert_eq!(hundredths(60), 100);
+    }
+
+    #[test]
+    fn test_record() {
+        let mut data = Vec::new();
+        let mut recorder = Recorder::new(&mut data, 2, Effects::default());
+        let palette = Palette::default();
+        for lit in &[0, 0, 0, 1, 2, 2] {
+            recorder.push(&picture(*lit), &palette).unwrap();
+        }
+        assert_eq!(recorder.frames(), 6);
+        recorder.finish().unwrap();
+
+        let decoder = image::gif::GifDecoder::new(&data[..]).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        let delays: Vec<(u32, u32)> = frames.iter().map(|f| f.delay().numer_denom_ms()).collect();
+        assert_eq!(delays, vec![(50, 1), (20, 1), (30, 1)]);
+
+        let first = frames[0].buffer();
+        assert_eq!(first.dimensions(), (16, 8));
+        assert_eq!(first.get_pixel(1, 1), &Rgba([0x00, 0xFF, 0x00, 0xFF]));
+        assert_eq!(first.get_pixel(2, 1), &Rgba([0x00, 0x00, 0x00, 0xFF]));
+    }
+
+    #[test]
+    fn test_quantize() {
+        let image = RgbaImage::from_fn(20, 20, |x, y| Rgba([x as u8 * 10, y as u8 * 10, 0, 0xFF]));
+        let frame = to_gif_frame(&image);
+        assert!(frame.palette.unwrap().len() <= 256 * 3);
+        assert_eq!(frame.buffer.len(), 400);
+    }
+
+    #[test]
+    fn test_long_still() {
+        let mut data = Vec::new();
+        let mut recorder = Recorder::new(&mut data, 1, Effects::default());
+        let palette = Palette::default();
+        // 700 s of the same picture, then another one
+        for _ in 0..700 * FRAMES_PER_SECOND {
+            recorder.push(&picture(0), &palette).unwrap();
+        }
+        recorder.push(&picture(1), &palette).unwrap();
+        recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(&data[..]).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert_eq!(delays, vec![65535, 4465, 2]);
+    }
+
+    #[test]
+    fn test_record_failure() {
+        struct FailingFlush;
+        impl Write for FailingFlush {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Err(io::ErrorKind::WriteZero.into())
+            }
+        }
+
+        let mut recorder = Recorder::new(FailingFlush, 1, Effects::default());
+        recorder.push(&picture(0), &Palette::default()).unwrap();
+        assert!(recorder.finish().is_err());
+    }
+
+    #[test]
+    fn test_record_nothing() {
+        let path = std::env::temp_dir().join(format!("chip8-test-{}.gif", std::process::id()));
+        let recorder = Recorder::new(DeferredFile::new(path.clone()), 2, Effects::default());
+        recorder.finish().unwrap();
+        assert!(!path.exists());
+
+        let mut recorder = Recorder::new(DeferredFile::new(path.clone()), 2, Effects::default());
+        recorder.push(&picture(0), &Palette::default()).unwrap();
+        recorder.finish().unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+use crate::emulator::display;
+use crate::emulator::ui::antiflicker::Blended;
+use crate::emulator::ui::effects::Effects;
+use crate::emulator::ui::palette::Palette;
+use crate::emulator::Framebuffer;
+use image::{Rgba, RgbaImage};
+
+/// What a frontend should show for the current frame.
+#[derive(Debug, Clone)]
+pub enum Picture {
+    /// The monochrome or multi-plane display, after anti-flicker.
+    Mono(Blended),
+    /// A MEGA-CHIP frame, which carries its own colours.
+    Color(display::Frame),
+}
+
+impl From<&Framebuffer> for Picture {
+    fn from(fb: &Framebuffer) -> Self {
+        Picture::Mono(Blended::from(fb))
+    }
+}
+
+impl Picture {
+    /// Resolution in emulated pixels.
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            Picture::Mono(b) => (b.width, b.height),
+            Picture::Color(f) => (f.width as usize, f.height as usize),
+        }
+    }
+
+    /// Draws the picture into an RGBA image, `scale` host pixels per emulated pixel.
+    pub fn rasterize(&self, palette: &Palette, scale: u32) -> RgbaImage {
+        let (width, height) = self.size();
+        let scale = scale.max(1);
+        RgbaImage::from_fn(width as u32 * scale, height as u32 * scale, |x, y| {
+            let i = (y / scale) as usize * width + (x / scale) as usize;
+            self.pixel(palette, i)
+        })
+    }
+
+    /// Rasterizes the picture and runs the effects over it.
+    pub fn render(&self, palette: &Palette, effects: &Effects, scale: u32) -> RgbaImage {
+        let mut image = self.rasterize(palette, scale);
+        effects.apply(&mut image, scale.max(1));
+        image
+    }
+
+    fn pixel(&self, palette: &Palette, i: usize) -> Rgba<u8> {
+        match self {
+            Picture::Mono(b) => {
+                let [r, g, b] = palette.shade(b.planes[i], b.intensity[i]);
+                Rgba([r, g, b, 0xFF])
+            }
+            Picture::Color(f) => {
+                let c = f.colors[i];
+                let alpha = ((c >> 24) & 0xFF) * f.alpha as u32 / 0xFF;
+                Rgba([(c >> 16) as u8, (c >> 8) as u8, c as u8, alpha as u8])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rasterize_mono() {
+        let mut fb = Framebuffer::new(4, 2, 1);
+        fb.set(1, 0, 1);
+        let palette = Palette::new("test", [1, 2, 3], vec![[10, 20, 30]]);
+
+        let img = Picture::Mono(Blended::from(&fb)).rasterize(&palette, 3);
+        assert_eq!(img.dimensions(), (12, 6));
+        assert_eq!(img.get_pixel(0, 0), &Rgba([1, 2, 3, 0xFF]));
+        assert_eq!(img.get_pixel(3, 0), &Rgba([10, 20, 30, 0xFF]));
+        assert_eq!(img.get_pixel(5, 2), &Rgba([10, 20, 30, 0xFF]));
+        assert_eq!(img.get_pixel(6, 0), &Rgba([1, 2, 3, 0xFF]));
+        assert_eq!(img.get_pixel(3, 3), &Rgba([1, 2, 3, 0xFF]));
+    }
+}
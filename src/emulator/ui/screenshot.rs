@@ -0,0 +1,55 @@
+use crate::emulator::common::{Error, Result};
+use crate::emulator::ui::antiflicker::Blended;
+use crate::emulator::ui::effects::Effects;
+use crate::emulator::ui::palette::Palette;
+use crate::emulator::ui::render::Picture;
+use crate::emulator::Framebuffer;
+use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_SCALE: u32 = 10;
+
+pub fn encode_png(image: RgbaImage) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut data, ImageOutputFormat::Png)
+        .map_err(|err| Error::Unexpected(Box::new(err)))?;
+    Ok(data)
+}
+
+/// Encodes the framebuffer as a PNG, `scale` image pixels per emulated pixel.
+pub fn framebuffer_png(fb: &Framebuffer, palette: &Palette, scale: u32) -> Result<Vec<u8>> {
+    encode_png(Picture::Mono(Blended::from(fb)).rasterize(palette, scale))
+}
+
+pub fn save(
+    picture: &Picture,
+    palette: &Palette,
+    effects: &Effects,
+    scale: u32,
+    path: &Path,
+) -> Result<()> {
+    let data = encode_png(picture.render(palette, effects, scale))?;
+    fs::write(path, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_framebuffer_png() {
+        let mut fb = Framebuffer::new(64, 32, 1);
+        fb.set(0, 0, 1);
+
+        let data = framebuffer_png(&fb, &Palette::default(), 2).unwrap();
+        assert_eq!(&data[1..4], b"PNG");
+
+        let img = image::load_from_memory(&data).unwrap().to_rgba();
+        assert_eq!(img.dimensions(), (128, 64));
+        assert_eq!(img.get_pixel(1, 1), &image::Rgba([0x00, 0xFF, 0x00, 0xFF]));
+        assert_eq!(img.get_pixel(2, 0), &image::Rgba([0x00, 0x00, 0x00, 0xFF]));
+    }
+}
@@ -0,0 +1,343 @@
+use crate::emulator;
+use crate::emulator::common::Result;
+use crate::emulator::display;
+use crate::emulator::display::Rect;
+use crate::emulator::movie::Movie;
+use crate::emulator::ui::antiflicker::{AntiFlicker, Mode};
+use crate::emulator::ui::audio::{AudioSink, Buzzer, NullSink};
+use crate::emulator::ui::render::Picture;
+use std::path::PathBuf;
+
+/// Drives an emulator one frame at a time and keeps track of what should be on screen. Shared
+/// by the GUI and the headless tools so both see exactly the same frames.
+pub struct Session {
+    anti_flicker: AntiFlicker,
+    color_frame: Option<display::Frame>,
+    frames: u64,
+    dirty: Option<Rect>,
+    audio: Box<dyn AudioSink>,
+    recording: Option<(Movie, PathBuf)>,
+    replay: Option<(Movie, usize)>, // and the next event to play
+}
+
+impl Session {
+    pub fn new(anti_flicker: Mode) -> Self {
+        Session {
+            anti_flicker: AntiFlicker::new(anti_flicker),
+            color_frame: None,
+            frames: 0,
+            dirty: None,
+            audio: Box::new(NullSink),
+            recording: None,
+            replay: None,
+        }
+    }
+
+    /// Records key presses into `movie`, which is saved to `path` by `finish`. The emulator
+    /// should have been set up with [`Movie::prepare`].
+    pub fn record_movie(&mut self, movie: Movie, path: PathBuf) {
+        self.recording = Some((movie, path));
+    }
+
+    /// Presses and releases keys as recorded in `movie`. Keys from the player are ignored
+    /// until the movie is over.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.replay = Some((movie, 0));
+    }
+
+    /// Whether a movie is being played and has not reached its end yet.
+    pub fn replaying(&self) -> bool {
+        self.replay
+            .as_ref()
+            .is_some_and(|(movie, _)| self.frames < movie.frames)
+    }
+
+    /// Passes a key from the player to the emulator, recording it if a movie is being made.
+    pub fn key(&mut self, emu: &mut emulator::Emulator, key: emulator::Input, pressed: bool) {
+        if self.replaying() {
+            return;
+        }
+
+        if let Some((movie, _)) = self.recording.as_mut() {
+            movie.record(self.frames, key, pressed);
+        }
+        press(emu, key, pressed);
+    }
+
+    /// Plays the buzzer through `audio` from now on.
+    pub fn set_audio(&mut self, audio: Box<dyn AudioSink>) {
+        self.audio = audio;
+    }
+
+    /// Changes how the buzzer sounds.
+    pub fn set_buzzer(&mut self, buzzer: Buzzer) {
+        self.audio.set_buzzer(buzzer);
+    }
+
+    /// Flushes the audio sink and saves the movie being recorded. Call once the session is
+    /// over.
+    pub fn finish(&mut self) -> Result<()> {
+        self.audio.finish()?;
+        if let Some((mut movie, path)) = self.recording.take() {
+            movie.frames = self.frames;
+            movie.save(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Number of frames run so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The part of the picture that changed during the last frame. Blending anti-flicker modes
+    /// fade pixels over several frames, so with those the whole picture counts as changed.
+    pub fn dirty(&self) -> Option<Rect> {
+        match self.anti_flicker.mode() {
+            Mode::Or(_) | Mode::Decay(_) => {
+                let (width, height) = self.picture().size();
+                Some(Rect::new(0, 0, width, height))
+            }
+            _ => self.dirty,
+        }
+    }
+
+    pub fn anti_flicker_mut(&mut self) -> &mut AntiFlicker {
+        &mut self.anti_flicker
+    }
+
+    /// Counts the timers down, executes `steps` instructions and ends the frame. Returns `false`
+    /// once the program has exited.
+    pub fn run_frame(&mut self, emu: &mut emulator::Emulator, steps: usize) -> Result<bool> {
+        if self.frames == 0 {
+            // start from the blank screen so there is a picture before the first draw
+            self.anti_flicker.push(emu.framebuffer().clone());
+        }
+
+        if let Some((movie, next)) = self.replay.as_mut() {
+            for event in movie.take_events(next, self.frames) {
+                press(emu, event.key, event.pressed);
+            }
+        }
+
+        emu.start_frame();
+        if let Some(emulator::Step::SoundOff) = emu.tick_timers() {
+            self.audio.set_tone(false);
+        }
+        for _i in 0..steps {
+            match emu.step()? {
+                Some(emulator::Step::Exit) => return Ok(false),
+                Some(emulator::Step::Draw(fb)) => {
+                    self.anti_flicker.push(fb);
+                    self.color_frame = None;
+                }
+                Some(emulator::Step::DrawColor(f)) => {
+                    self.color_frame = Some(f);
+                }
+                Some(emulator::Step::PlaySample(sample)) => self.audio.set_sample(Some(sample)),
+                Some(emulator::Step::StopSample) => self.audio.set_sample(None),
+                Some(emulator::Step::SoundOn) => self.audio.set_tone(true),
+                Some(emulator::Step::SoundOff) => self.audio.set_tone(false),
+                _ => {}
+            }
+        }
+
+        // the sink gets one tick per frame, with the tone the frame's sound events left it at
+        self.audio.tick()?;
+
+        self.anti_flicker.vblank();
+        self.dirty = emu.take_dirty();
+        if self.frames == 0 {
+            let (width, height) = self.picture().size();
+            self.dirty = display::extend(self.dirty, Rect::new(0, 0, width, height));
+        }
+        self.frames += 1;
+        Ok(true)
+    }
+
+    pub fn picture(&self) -> Picture {
+        match self.color_frame.as_ref() {
+            Some(f) => Picture::Color(f.clone()),
+            None => Picture::Mono(self.anti_flicker.output()),
+        }
+    }
+}
+
+fn press(emu: &mut emulator::Emulator, key: emulator::Input, pressed: bool) {
+    if pressed {
+        emu.key_press(key);
+    } else {
+        emu.key_release(key);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::ui::audio::{WavSink, SAMPLES_PER_TICK};
+    use crate::emulator::ui::text;
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Seek, SeekFrom, Write};
+    use std::rc::Rc;
+
+    // V0 = 3, ST = V0, then loop forever
+    const BUZZER_ROM: [u8; 6] = [0x60, 0x03, 0xF0, 0x18, 0x12, 0x04];
+
+    #[derive(Clone, Default)]
+    struct SharedWav(Rc<RefCell<Cursor<Vec<u8>>>>);
+
+    impl Write for SharedWav {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for SharedWav {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.borrow_mut().seek(pos)
+        }
+    }
+
+    fn record_wav(rom: &[u8], frames: u64) -> Vec<u8> {
+        let mut emu = emulator::Emulator::new(rom).unwrap();
+        let wav = SharedWav::default();
+        let mut session = Session::new(Mode::default());
+        session.set_audio(Box::new(WavSink::new(wav.clone()).unwrap()));
+
+        while session.frames() < frames && session.run_frame(&mut emu, 1).unwrap() {}
+        session.finish().unwrap();
+
+        let data = wav.0.borrow().get_ref().clone();
+        data
+    }
+
+    #[test]
+    fn test_timers_per_frame() {
+        let mut emu = emulator::Emulator::new(&BUZZER_ROM[..]).unwrap();
+        for _ in 0..10 {
+            emu.step().unwrap();
+        }
+        assert!(emu.sound_active());
+
+        // ten instructions a frame still sound the buzzer for three frames
+        let mut emu = emulator::Emulator::new(&BUZZER_ROM[..]).unwrap();
+        let mut session = Session::new(Mode::default());
+        let mut sounding = Vec::new();
+        for _ in 0..5 {
+            session.run_frame(&mut emu, 10).unwrap();
+            sounding.push(emu.sound_active());
+        }
+        assert_eq!(sounding, [true, true, true, false, false]);
+    }
+
+    // waits for a key and draws its digit, forever
+    const KEY_ROM: [u8; 8] = [0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+
+    #[test]
+    fn test_movie_replay() {
+        let path = std::env::temp_dir().join(format!("chip8-test-{}.movie", std::process::id()));
+
+        let mut emu = emulator::Emulator::new(&KEY_ROM[..]).unwrap();
+        let movie = Movie::new(&emu, 1, 1);
+        movie.prepare(&mut emu).unwrap();
+        let mut session = Session::new(Mode::default());
+        session.record_movie(movie, path.clone());
+        for frame in 0..20 {
+            match frame {
+                3 => session.key(&mut emu, emulator::Input::Key7, true),
+                5 => session.key(&mut emu, emulator::Input::Key7, false),
+                9 => session.key(&mut emu, emulator::Input::KeyA, true),
+                _ => {}
+            }
+            session.run_frame(&mut emu, 1).unwrap();
+        }
+        session.finish().unwrap();
+        let recorded = emu.framebuffer().clone();
+
+        let movie = Movie::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(movie.frames, 20);
+        assert_eq!(movie.events.len(), 3);
+
+        let mut emu = emulator::Emulator::new(&KEY_ROM[..]).unwrap();
+        movie.prepare(&mut emu).unwrap();
+        let mut session = Session::new(Mode::default());
+        session.play_movie(movie);
+        while session.replaying() {
+            // live keys are ignored during the replay
+            session.key(&mut emu, emulator::Input::Key1, true);
+            session.run_frame(&mut emu, 1).unwrap();
+        }
+
+        assert_ne!(recorded, emulator::Framebuffer::new(64, 32, 1));
+        assert!(
+            emu.framebuffer() == &recorded,
+            "{}",
+            text::diff(&recorded, emu.framebuffer())
+        );
+    }
+
+    // waits for key 0 with EX9E, then draws a 0
+    const SKP_ROM: [u8; 10] = [0xE0, 0x9E, 0x12, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x08];
+
+    #[test]
+    fn test_short_tap() {
+        let mut emu = emulator::Emulator::new(&SKP_ROM[..]).unwrap();
+        let mut session = Session::new(Mode::default());
+        session.run_frame(&mut emu, 10).unwrap();
+
+        // pressed and released between two frames, yet the program still sees it
+        session.key(&mut emu, emulator::Input::Key0, true);
+        session.key(&mut emu, emulator::Input::Key0, false);
+        session.run_frame(&mut emu, 10).unwrap();
+        assert_ne!(emu.framebuffer(), &emulator::Framebuffer::new(64, 32, 1));
+        assert!(emu.keypad().is_pressed(0));
+        session.run_frame(&mut emu, 10).unwrap();
+        assert!(!emu.keypad().is_pressed(0));
+    }
+
+    #[test]
+    fn test_dirty() {
+        // I = digit 0, draw it at (2, 3) and at (20, 10), then loop forever
+        let rom = [
+            0xF0, 0x29, 0x61, 0x02, 0x62, 0x03, 0xD1, 0x25, 0x63, 0x14, 0x64, 0x0A, 0xD3, 0x45,
+            0x12, 0x0E,
+        ];
+        let mut emu = emulator::Emulator::new(&rom[..]).unwrap();
+        let mut session = Session::new(Mode::default());
+
+        // the first frame has to be shown in full
+        session.run_frame(&mut emu, 0).unwrap();
+        assert_eq!(session.dirty(), Some(Rect::new(0, 0, 64, 32)));
+
+        session.run_frame(&mut emu, 7).unwrap();
+        assert_eq!(session.dirty(), Some(Rect::new(2, 3, 22, 12)));
+
+        session.run_frame(&mut emu, 1).unwrap();
+        assert_eq!(session.dirty(), None);
+    }
+
+    #[test]
+    fn test_buzzer_wav() {
+        let wav = record_wav(&BUZZER_ROM, 8);
+        assert_eq!(wav.len(), 44 + 8 * SAMPLES_PER_TICK * 2);
+
+        // the tone starts on the tick that sets the sound timer and lasts three ticks; only the
+        // second half of each tick is checked since the release fades into the next one
+        let tick_on = |n: usize| {
+            let start = 44 + n * SAMPLES_PER_TICK * 2 + SAMPLES_PER_TICK;
+            wav[start..start + SAMPLES_PER_TICK].iter().any(|b| *b != 0)
+        };
+        let ticks: Vec<bool> = (0..8).map(tick_on).collect();
+        assert_eq!(
+            ticks,
+            vec![false, true, true, true, false, false, false, false]
+        );
+
+        assert!(wav == include_bytes!("testdata/buzzer.wav").to_vec());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::emulator::ui::graphics::Protocol;
+use crate::emulator::ui::palette::Palette;
+use crate::emulator::ui::render::Picture;
+use crate::emulator::ui::text::{self, Style};
+
+/// How a terminal frontend shows the picture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    /// Plain characters, without colour.
+    Text(Style),
+    /// Coloured half blocks.
+    Ansi,
+    /// An inline image, pixel for pixel.
+    Graphics(Protocol),
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::Text(Style::default())
+    }
+}
+
+impl Output {
+    /// Parses `blocks`, `half-blocks`, `braille`, `ansi`, `sixel` or `kitty`.
+    pub fn from_name(name: &str) -> Option<Output> {
+        match name {
+            "ansi" => Some(Output::Ansi),
+            _ => Style::from_name(name)
+                .map(Output::Text)
+                .or_else(|| Protocol::from_name(name).map(Output::Graphics)),
+        }
+    }
+
+    /// Renders the picture. `scale` only matters for inline images.
+    pub fn render(&self, picture: &Picture, palette: &Palette, scale: u32) -> String {
+        match self {
+            Output::Text(style) => text::render(picture, *style),
+            Output::Ansi => text::render_ansi(picture, palette),
+            Output::Graphics(protocol) => protocol.encode(&picture.rasterize(palette, scale)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            Output::from_name("braille"),
+            Some(Output::Text(Style::Braille))
+        );
+        assert_eq!(Output::from_name("ansi"), Some(Output::Ansi));
+        assert_eq!(
+            Output::from_name("kitty"),
+            Some(Output::Graphics(Protocol::Kitty))
+        );
+        assert_eq!(Output::from_name("png"), None);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::emulator::ui::palette::Palette;
+use crate::emulator::ui::render::Picture;
+use crate::emulator::Framebuffer;
+use std::fmt::Write;
+
+const BRAILLE_BASE: u32 = 0x2800;
+// dot bits of a Braille cell, indexed by [y][x]
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// How pixels are packed into characters.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Style {
+    /// One character per pixel, like `Display` for [`Framebuffer`].
+    Blocks,
+    /// Two pixels per character, stacked vertically, using `▀`, `▄` and `█`.
+    #[default]
+    HalfBlocks,
+    /// Eight pixels per character, two across and four down.
+    Braille,
+}
+
+impl Style {
+    pub fn from_name(name: &str) -> Option<Style> {
+        match name {
+            "blocks" => Some(Style::Blocks),
+            "half-blocks" => Some(Style::HalfBlocks),
+            "braille" => Some(Style::Braille),
+            _ => None,
+        }
+    }
+
+    /// Pixels covered by one character.
+    pub fn cell_size(self) -> (usize, usize) {
+        match self {
+            Style::Blocks => (1, 1),
+            Style::HalfBlocks => (1, 2),
+            Style::Braille => (2, 4),
+        }
+    }
+}
+
+/// Which pixels of a picture are lit.
+struct Grid {
+    width: usize,
+    height: usize,
+    lit: Vec<bool>,
+}
+
+impl Grid {
+    fn new(picture: &Picture) -> Self {
+        let (width, height) = picture.size();
+        let lit = match picture {
+            Picture::Mono(b) => b.intensity.iter().map(|i| *i > 0.0).collect(),
+            Picture::Color(f) => f.colors.iter().map(|c| *c & 0x00FF_FFFF != 0).collect(),
+        };
+        Grid { width, height, lit }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.lit[y * self.width + x]
+    }
+
+    fn cell(&self, style: Style, cx: usize, cy: usize) -> char {
+        let (x, y) = (cx * style.cell_size().0, cy * style.cell_size().1);
+        match style {
+            Style::Blocks => {
+                if self.get(x, y) {
+                    '█'
+                } else {
+                    '·'
+                }
+            }
+            Style::HalfBlocks => match (self.get(x, y), self.get(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            },
+            Style::Braille => {
+                let mut bits = 0;
+                for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                    for (dx, bit) in row.iter().enumerate() {
+                        if self.get(x + dx, y + dy) {
+                            bits |= bit;
+                        }
+                    }
+                }
+                std::char::from_u32(BRAILLE_BASE + bits).unwrap_or(' ')
+            }
+        }
+    }
+
+    fn lines(&self, style: Style) -> Vec<String> {
+        let (w, h) = style.cell_size();
+        let (columns, rows) = (self.width.div_ceil(w), self.height.div_ceil(h));
+        (0..rows)
+            .map(|cy| (0..columns).map(|cx| self.cell(style, cx, cy)).collect())
+            .collect()
+    }
+}
+
+/// Draws the lit pixels of the picture as text, one line per row of characters.
+pub fn render(picture: &Picture, style: Style) -> String {
+    Grid::new(picture).lines(style).join("\n")
+}
+
+/// Draws the picture in colour with half blocks and 24-bit ANSI escape codes. Every line ends
+/// by resetting the colours.
+pub fn render_ansi(picture: &Picture, palette: &Palette) -> String {
+    let image = picture.rasterize(palette, 1);
+    let (width, height) = image.dimensions();
+    let mut out = String::new();
+
+    for y in (0..height).step_by(2) {
+        let mut current = None;
+        for x in 0..width {
+            let top = image.get_pixel(x, y);
+            let bottom = if y + 1 < height {
+                image.get_pixel(x, y + 1)
+            } else {
+                top
+            };
+
+            let colors = (top, bottom);
+            if current != Some(colors) {
+                let _ = write!(
+                    out,
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                    top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                );
+                current = Some(colors);
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\n");
+    }
+
+    out
+}
+
+/// Shows what differs between two framebuffers: the expected picture, the actual one and the
+/// pixels that do not match, side by side. Meant for test failure messages.
+pub fn diff(expected: &Framebuffer, actual: &Framebuffer) -> String {
+    let expected_grid = Grid::new(&Picture::from(expected));
+    let actual_grid = Grid::new(&Picture::from(actual));
+    let width = expected_grid.width.max(actual_grid.width);
+    let height = expected_grid.height.max(actual_grid.height);
+    let changed = Grid {
+        width,
+        height,
+        lit: (0..width * height)
+            .map(|i| {
+                expected_grid.get(i % width, i / width) != actual_grid.get(i % width, i / width)
+            })
+            .collect(),
+    };
+
+    let columns = [
+        ("expected", expected_grid.lines(Style::HalfBlocks)),
+        ("actual", actual_grid.lines(Style::HalfBlocks)),
+        ("changed", changed.lines(Style::HalfBlocks)),
+    ];
+    let pad = |s: &str, name: &str| {
+        let n = width.max(name.len());
+        format!("{}{}", s, " ".repeat(n.saturating_sub(s.chars().count())))
+    };
+
+    let mut out = String::new();
+    let header: Vec<String> = columns.iter().map(|(name, _)| pad(name, name)).collect();
+    let _ = writeln!(out, "{}", header.join(" | ").trim_end());
+    for row in 0..height.div_ceil(2) {
+        let line: Vec<String> = columns
+            .iter()
+            .map(|(name, lines)| pad(lines.get(row).map_or("", |l| l.as_str()), name))
+            .collect();
+        let _ = writeln!(out, "{}", line.join(" | ").trim_end());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn framebuffer(lit: &[(usize, usize)]) -> Framebuffer {
+        let mut fb = Framebuffer::new(4, 4, 1);
+        for (x, y) in lit {
+            fb.set(*x, *y, 1);
+        }
+        fb
+    }
+
+    #[test]
+    fn test_render_styles() {
+        let picture = Picture::from(&framebuffer(&[(0, 0), (1, 1), (1, 2), (3, 3)]));
+
+        assert_eq!(render(&picture, Style::Blocks), "█···\n·█··\n·█··\n···█");
+        assert_eq!(render(&picture, Style::HalfBlocks), "▀▄  \n ▀ ▄");
+        assert_eq!(render(&picture, Style::Braille), "⠱⢀");
+    }
+
+    #[test]
+    fn test_render_odd_size() {
+        let mut fb = Framebuffer::new(3, 3, 1);
+        fb.set(2, 2, 1);
+        let picture = Picture::from(&fb);
+
+        assert_eq!(render(&picture, Style::HalfBlocks), "   \n  ▀");
+        assert_eq!(render(&picture, Style::Braille), "⠀⠄");
+    }
+
+    #[test]
+    fn test_render_ansi() {
+        let mut fb = Framebuffer::new(2, 2, 1);
+        fb.set(0, 0, 1);
+        let palette = Palette::new("test", [0, 0, 0], vec![[255, 255, 255]]);
+
+        let out = render_ansi(&Picture::from(&fb), &palette);
+        assert_eq!(
+            out,
+            "\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m▀\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m▀\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let out = diff(&framebuffer(&[(0, 0)]), &framebuffer(&[(0, 0), (2, 1)]));
+        assert_eq!(
+            out,
+            "expected | actual | changed\n▀        | ▀ ▄    |   ▄\n         |        |\n"
+        );
+    }
+}